@@ -4,14 +4,17 @@ extern crate test;
 extern crate clap;
 extern crate liboskar;
 
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use test::test::Bencher;
 
 use clap::App;
 
 use liboskar::gitignore::*;
 use liboskar::prelude::*;
+use liboskar::types::{NamePair, Units};
 
 #[bench]
 fn bench_cli_options(b: &mut Bencher) {
@@ -42,39 +45,36 @@ fn bench_processors(b: &mut Bencher) {
 
 #[bench]
 fn bench_traversal_size(b: &mut Bencher) {
-    let p = PathBuf::from("src/testdata");
-    b.iter(|| read_size(&p, None, &None, false, false))
+    let opts = ScanOptions::new("src/testdata");
+    b.iter(|| scan_each(&opts, &mut |_: NamePair| {}))
 }
 
 #[bench]
 fn bench_traversal(b: &mut Bencher) {
-    let p = PathBuf::from("src/testdata");
-    b.iter(|| read_all(&p, 4, None, None, &None, false, false))
+    let opts = ScanOptions::new("src/testdata");
+    b.iter(|| scan(&opts))
 }
 
 #[bench]
 fn bench_traversal_sort(b: &mut Bencher) {
-    let p = PathBuf::from("src/testdata");
-    b.iter(|| {
-        let v = read_all(&p, 4, None, None, &None, false, false);
-        v.sort(None, None, false, None)
-    })
+    let opts = ScanOptions::new("src/testdata");
+    b.iter(|| scan(&opts).unwrap().sort(None, None, false, None))
 }
 
 #[bench]
 fn bench_traversal_artifacts(b: &mut Bencher) {
-    let p = PathBuf::from("src/testdata");
-    b.iter(|| read_all(&p, 4, None, None, &None, false, true))
+    let opts = ScanOptions::new("src/testdata").artifacts_only(true);
+    b.iter(|| scan(&opts))
 }
 
 #[bench]
 fn bench_extension_regex(b: &mut Bencher) {
-    let metadata = fs::metadata("src/main.rs").unwrap();
+    let mode = fs::metadata("src/main.rs").unwrap().permissions().mode();
     b.iter(|| {
         is_artifact(
-            "libdoggo.rlib",
-            "target/release/libdoggo.rlib",
-            &metadata,
+            OsStr::new("libdoggo.rlib"),
+            Path::new("target/release/libdoggo.rlib"),
+            mode,
             false,
             &None,
         )
@@ -134,5 +134,5 @@ fn bench_get_metadata(b: &mut Bencher) {
 #[bench]
 fn bench_parser(b: &mut Bencher) {
     let cli_input = "1M";
-    b.iter(|| threshold(Some(cli_input)))
+    b.iter(|| threshold(Some(cli_input), Units::Iec))
 }
@@ -0,0 +1,486 @@
+//! A cache of directory listings, so that rescanning a large tree only reads the directories that
+//! have changed since the last scan.
+//!
+//! Each directory is stored along with its modification and status change times, which change
+//! whenever an entry is added, removed or renamed. A directory whose times are unchanged is
+//! listed from the cache rather than read again, and neither are the files in it. Files that are
+//! changed in place don't touch the directory they are in, so their sizes are only picked up
+//! again once something else in that directory changes.
+
+use error::{Internal, Warnings};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::Metadata;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::{EntryCount, FileSize, Times, Totals};
+use utils::{dev_ino, nlink, parse_field, path_bytes, path_from_bytes, size, Symlinks};
+
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::PermissionsExt;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// The first line of a cache file, so that other files aren't mistaken for one.
+const CACHE_HEADER: &str = "sn cache 2";
+
+/// What kind of entry something in a directory is. Symlinks that are followed take the kind of
+/// what they point to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    /// A FIFO, socket or device file.
+    Special,
+    /// Something whose type couldn't be read.
+    Other,
+}
+
+impl Kind {
+    pub fn is_file(self) -> bool {
+        self == Kind::File
+    }
+
+    pub fn is_dir(self) -> bool {
+        self == Kind::Dir
+    }
+
+    pub fn is_symlink(self) -> bool {
+        self == Kind::Symlink
+    }
+
+    fn tag(self) -> char {
+        match self {
+            Kind::File => 'f',
+            Kind::Dir => 'd',
+            Kind::Symlink => 'l',
+            Kind::Special => 's',
+            Kind::Other => 'o',
+        }
+    }
+
+    fn from_tag(tag: &[u8]) -> Option<Kind> {
+        match tag {
+            b"f" => Some(Kind::File),
+            b"d" => Some(Kind::Dir),
+            b"l" => Some(Kind::Symlink),
+            b"s" => Some(Kind::Special),
+            b"o" => Some(Kind::Other),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a scan needs from the metadata of a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileInfo {
+    pub len: u64,
+    pub blocks: u64,
+    pub times: Times,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+    pub mode: u32,
+}
+
+impl FileInfo {
+    pub fn from_metadata(m: &Metadata) -> FileInfo {
+        let (dev, ino) = dev_ino(m);
+        FileInfo {
+            len: size(m, false),
+            blocks: size(m, true),
+            times: Times::from_metadata(m),
+            dev,
+            ino,
+            nlink: nlink(m),
+            mode: mode(m),
+        }
+    }
+
+    /// Disk usage (allocated blocks) with `blocks`, otherwise the apparent size.
+    pub fn size(&self, blocks: bool) -> u64 {
+        if blocks {
+            self.blocks
+        } else {
+            self.len
+        }
+    }
+
+    /// The totals for a file.
+    pub fn file_totals(&self, blocks: bool) -> Totals {
+        Totals {
+            size: FileSize::new(self.size(blocks)),
+            count: EntryCount {
+                files: 1,
+                ..EntryCount::default()
+            },
+            times: self.times,
+            own: Some(*self),
+        }
+    }
+
+    /// The totals for a directory itself, apart from its contents, as in `Totals::dir_itself`.
+    pub fn dir_totals(&self, blocks: bool) -> Totals {
+        Totals {
+            size: FileSize::new(if blocks { self.blocks } else { 0 }),
+            count: EntryCount::default(),
+            times: self.times,
+            own: None,
+        }
+    }
+
+    fn to_field(self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.len,
+            self.blocks,
+            self.times.modified,
+            self.times.accessed,
+            self.dev,
+            self.ino,
+            self.nlink,
+            self.mode
+        )
+    }
+
+    fn from_field(field: &[u8]) -> Option<FileInfo> {
+        let mut values = field.split(|c| *c == b',');
+        Some(FileInfo {
+            len: parse_field(values.next())?,
+            blocks: parse_field(values.next())?,
+            times: Times {
+                modified: parse_field(values.next())?,
+                accessed: parse_field(values.next())?,
+            },
+            dev: parse_field(values.next())?,
+            ino: parse_field(values.next())?,
+            nlink: parse_field(values.next())?,
+            mode: parse_field(values.next())?,
+        })
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn mode(m: &Metadata) -> u32 {
+    m.permissions().mode()
+}
+
+#[cfg(target_os = "windows")]
+fn mode(_: &Metadata) -> u32 {
+    0
+}
+
+/// Something in a directory. Only files carry their metadata, and only if it could be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Child {
+    pub name: OsString,
+    pub kind: Kind,
+    pub info: Option<FileInfo>,
+}
+
+/// The contents of a directory, along with the directory's own metadata.
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub own: Option<FileInfo>,
+    pub children: Vec<Child>,
+}
+
+/// When a directory last changed, down to the nanosecond where the platform allows, and whether
+/// symlinks in it were followed when it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: i128,
+    changed: i128,
+    follow: bool,
+}
+
+impl Stamp {
+    #[cfg(unix)]
+    fn new(m: &Metadata, follow: bool) -> Stamp {
+        let nanos = |secs: i64, nsecs: i64| i128::from(secs) * 1_000_000_000 + i128::from(nsecs);
+        Stamp {
+            modified: nanos(m.mtime(), m.mtime_nsec()),
+            changed: nanos(m.ctime(), m.ctime_nsec()),
+            follow,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(m: &Metadata, follow: bool) -> Stamp {
+        let nanos = m
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as i128);
+        Stamp {
+            modified: nanos,
+            changed: 0,
+            follow,
+        }
+    }
+}
+
+/// A directory's listing, along with when it was read. A directory that changes again within the
+/// same tick of the filesystem's clock keeps the same times, so the listing is only trusted if
+/// the directory had already been left alone for `SETTLE` nanoseconds when it was read.
+#[derive(Debug, Clone)]
+struct Cached {
+    stamp: Stamp,
+    read: i128,
+    children: Vec<Child>,
+}
+
+/// Generous enough for filesystems that only keep times to the second or two.
+const SETTLE: i128 = 2_000_000_000;
+
+impl Cached {
+    fn settled(&self) -> bool {
+        self.stamp.modified.max(self.stamp.changed) + SETTLE <= self.read
+    }
+}
+
+fn now() -> i128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i128)
+}
+
+/// Directory listings from the last scan, along with the ones read during this one. This can be
+/// shared between threads.
+#[derive(Debug)]
+pub struct DirCache {
+    path: PathBuf,
+    old: HashMap<PathBuf, Cached>,
+    new: Mutex<HashMap<PathBuf, Cached>>,
+}
+
+impl DirCache {
+    /// Load the cache at `path`. A missing cache is treated as empty, as is one that can't be
+    /// read or parsed, with a warning; either way it will be replaced on `save`.
+    pub fn open<P: AsRef<Path>>(path: P, warnings: &Warnings) -> DirCache {
+        let read = fs::read(path.as_ref()).and_then(|b| {
+            parse_cache(&b)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a valid cache file"))
+        });
+        let old = match read {
+            Ok(old) => old,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warnings.warn(&Internal::Io {
+                    path: path.as_ref().to_path_buf(),
+                    source: e,
+                });
+                HashMap::new()
+            }
+        };
+        DirCache {
+            path: path.as_ref().to_path_buf(),
+            old,
+            new: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Write the cache back to where it was loaded from. Directories under `roots` that weren't
+    /// read during this scan have gone away (or are now excluded), so they are dropped; the rest
+    /// of the old cache is kept.
+    pub fn save(&self, roots: &[PathBuf]) -> io::Result<()> {
+        let new = match self.new.lock() {
+            Ok(n) => n,
+            Err(e) => e.into_inner(),
+        };
+        let mut out = format!("{}\n", CACHE_HEADER).into_bytes();
+        let kept = self
+            .old
+            .iter()
+            .filter(|&(p, _)| !new.contains_key(p) && !roots.iter().any(|r| p.starts_with(r)));
+        for (dir, cached) in new.iter().chain(kept) {
+            out.extend(
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t",
+                    cached.stamp.modified,
+                    cached.stamp.changed,
+                    cached.stamp.follow,
+                    cached.read,
+                    cached.children.len()
+                )
+                .into_bytes(),
+            );
+            out.extend_from_slice(&path_bytes(dir));
+            out.push(b'\0');
+            for child in &cached.children {
+                let info = child
+                    .info
+                    .map_or_else(|| "-".to_string(), FileInfo::to_field);
+                out.extend(format!("{}\t{}\t", child.kind.tag(), info).into_bytes());
+                out.extend_from_slice(&path_bytes(&child.name));
+                out.push(b'\0');
+            }
+        }
+        // write to a file alongside the cache and move it into place, so that a scan that is
+        // interrupted leaves the old cache rather than half of a new one
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let written = fs::File::create(&tmp)
+            .and_then(|mut f| f.write_all(&out).and_then(|_| f.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+
+    fn get(&self, dir: &Path, stamp: Stamp) -> Option<Cached> {
+        match self.old.get(dir) {
+            Some(cached) if cached.stamp == stamp && cached.settled() => Some(cached.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, dir: &Path, cached: Cached) {
+        if let Ok(mut new) = self.new.lock() {
+            new.insert(dir.to_path_buf(), cached);
+        }
+    }
+}
+
+/// Write the cache back after scanning `roots`, if there is one. Failing to do so only means the
+/// next scan is slower, so this is just a warning.
+pub fn save_cache(cache: Option<&DirCache>, roots: &[PathBuf], warnings: &Warnings) {
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save(roots) {
+            warnings.note(format!("could not save cache: {}", e));
+        }
+    }
+}
+
+/// List the contents of a directory, using the cache if the directory hasn't changed since it
+/// was cached. Files that symlinks point to are looked through if `symlinks` follows them.
+/// Entries that can't be read are left out with a warning; a listing missing any of them isn't
+/// cached, so that the next scan tries them again.
+pub fn list_dir(
+    dir: &Path,
+    symlinks: &Symlinks,
+    cache: Option<&DirCache>,
+    warnings: &Warnings,
+) -> io::Result<Listing> {
+    let read = now();
+    let own = fs::metadata(dir).ok();
+    let stamp = own.as_ref().map(|m| Stamp::new(m, symlinks.follow()));
+    let own = own.as_ref().map(FileInfo::from_metadata);
+
+    if let (Some(cache), Some(stamp)) = (cache, stamp) {
+        if let Some(cached) = cache.get(dir, stamp) {
+            let children = cached.children.clone();
+            cache.insert(dir, cached);
+            return Ok(Listing { own, children });
+        }
+    }
+
+    let mut children = Vec::new();
+    let mut complete = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warnings.warn(&Internal::Io {
+                    path: dir.to_path_buf(),
+                    source: e,
+                });
+                complete = false;
+                continue;
+            }
+        };
+        let kind = match symlinks.file_type(&entry) {
+            Ok(t) if t.is_file() => Kind::File,
+            Ok(t) if t.is_dir() => Kind::Dir,
+            Ok(t) if t.is_symlink() => Kind::Symlink,
+            Ok(_) => Kind::Special,
+            Err(_) => Kind::Other,
+        };
+        // if this fails, it's probably because the entry is a broken symlink
+        let info = if kind.is_file() {
+            symlinks
+                .metadata(&entry)
+                .ok()
+                .map(|m| FileInfo::from_metadata(&m))
+        } else {
+            None
+        };
+        children.push(Child {
+            name: entry.file_name(),
+            kind,
+            info,
+        });
+    }
+
+    if let (Some(cache), Some(stamp), true) = (cache, stamp, complete) {
+        cache.insert(
+            dir,
+            Cached {
+                stamp,
+                read,
+                children: children.clone(),
+            },
+        );
+    }
+    Ok(Listing { own, children })
+}
+
+fn parse_cache(b: &[u8]) -> Option<HashMap<PathBuf, Cached>> {
+    let rest = b
+        .strip_prefix(CACHE_HEADER.as_bytes())?
+        .strip_prefix(b"\n")?;
+    // every record ends with a NUL, so anything else was cut off part way through
+    if !rest.is_empty() && !rest.ends_with(b"\0") {
+        return None;
+    }
+    let mut records = rest.split(|c| *c == b'\0');
+    let mut dirs = HashMap::new();
+    while let Some(record) = records.next() {
+        if record.is_empty() {
+            break;
+        }
+        let mut fields = record.splitn(6, |c| *c == b'\t');
+        let stamp = Stamp {
+            modified: parse_field(fields.next())?,
+            changed: parse_field(fields.next())?,
+            follow: parse_field(fields.next())?,
+        };
+        let read = parse_field(fields.next())?;
+        let len: usize = parse_field(fields.next())?;
+        let dir = path_from_bytes(fields.next()?);
+        let mut children = Vec::with_capacity(len);
+        for _ in 0..len {
+            let mut fields = records.next()?.splitn(3, |c| *c == b'\t');
+            let kind = Kind::from_tag(fields.next()?)?;
+            let info = match fields.next()? {
+                b"-" => None,
+                field => Some(FileInfo::from_field(field)?),
+            };
+            children.push(Child {
+                name: path_from_bytes(fields.next()?).into_os_string(),
+                kind,
+                info,
+            });
+        }
+        dirs.insert(
+            dir,
+            Cached {
+                stamp,
+                read,
+                children,
+            },
+        );
+    }
+    Some(dirs)
+}
@@ -1,15 +1,16 @@
 use clap::Values;
-use colored::*;
 use error::*;
-use nom::IResult;
-use regex::Regex;
+use nom::{digit, IResult};
+use output::OutputFormat;
+use regex::bytes::Regex;
 use std::path::PathBuf;
-use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::{AgeFilter, TimeKind, Units};
 use utils::get_processors;
 
 /// Parse a string into a regular expression for the 'artifacts' subcommand. Adds ignores for
 /// typical version control directories if none are present.
-pub fn get_excludes(cli_excludes: Option<&str>) -> Regex {
+pub fn get_excludes(cli_excludes: Option<&str>) -> Result<Regex, Internal> {
     match cli_excludes {
         Some(s) => {
             let mut x = "(".to_string();
@@ -17,48 +18,48 @@ pub fn get_excludes(cli_excludes: Option<&str>) -> Regex {
             x.push_str(r")|(\.git|\.pijul|_darcs|\.hg|\.gnupg)$");
             check_regex(&x)
         }
-        _ => Regex::new(r"(\.git|\.pijul|_darcs|\.hg|\.gnupg)$").unwrap(), // ok because static
+        _ => Ok(Regex::new(r"(\.git|\.pijul|_darcs|\.hg|\.gnupg)$").unwrap()), // ok because static
     }
 }
 
-pub fn get_depth(depth_from_cli: Option<&str>) -> u8 {
-    if let Some(n) = depth_from_cli {
-        if let Ok(n) = n.parse::<u8>() {
-            n
-        } else {
-            eprintln!("{}", Internal::ParseNum);
-            exit(0x0f01);
-        }
-    } else {
-        2
+pub fn get_depth(depth_from_cli: Option<&str>) -> Result<u8, Internal> {
+    match depth_from_cli {
+        Some(n) => n
+            .parse::<u8>()
+            .map_err(|_| Internal::ParseNum(n.to_string())),
+        None => Ok(2),
     }
 }
 
-pub fn get_num(num_from_cli: Option<&str>) -> usize {
-    if let Some(num) = num_from_cli {
-        if let Ok(n) = num.parse::<usize>() {
-            n
-        } else {
-            eprintln!("{}", Internal::ParseNum);
-            exit(0x0f01);
-        }
-    } else {
-        8
+pub fn get_num(num_from_cli: Option<&str>) -> Result<usize, Internal> {
+    match num_from_cli {
+        Some(num) => num
+            .parse::<usize>()
+            .map_err(|_| Internal::ParseNum(num.to_string())),
+        None => Ok(8),
+    }
+}
+
+/// Parse the `--format` flag. Defaults to human-readable output; `--null` takes precedence over
+/// `--format`.
+pub fn get_format(format_from_cli: Option<&str>, null: bool) -> Result<OutputFormat, Internal> {
+    match format_from_cli {
+        _ if null => Ok(OutputFormat::Null),
+        Some("json") => Ok(OutputFormat::Json),
+        Some("csv") => Ok(OutputFormat::Csv),
+        Some("tsv") => Ok(OutputFormat::Tsv),
+        Some("human") | None => Ok(OutputFormat::Human),
+        Some(other) => Err(Internal::InvalidFormat(other.to_string())),
     }
 }
 
 /// If the user has supplied a string, parse it, otherwise, read the number of processors.
-pub fn get_threads(num_from_cli: Option<&str>) -> usize {
+pub fn get_threads(num_from_cli: Option<&str>) -> Result<usize, Internal> {
     match num_from_cli {
-        Some(num) => {
-            if let Ok(n) = num.parse::<usize>() {
-                n
-            } else {
-                eprintln!("{}", Internal::ParseNum);
-                exit(0x0f01);
-            }
-        }
-        _ => get_processors(),
+        Some(num) => num
+            .parse::<usize>()
+            .map_err(|_| Internal::ParseNum(num.to_string())),
+        _ => Ok(get_processors()),
     }
 }
 
@@ -66,9 +67,7 @@ pub fn get_dirs(paths_from_cli: Option<Values>) -> Vec<PathBuf> {
     if let Some(read) = paths_from_cli {
         read.map(PathBuf::from).collect()
     } else {
-        let mut v = Vec::new();
-        v.push(PathBuf::from("."));
-        v
+        vec![PathBuf::from(".")]
     }
 }
 
@@ -79,77 +78,239 @@ pub fn get_dir(path_from_cli: Option<&str>) -> PathBuf {
     }
 }
 
-/// Parse a threshold from a command-line flag.
+/// Parse the `--bytes`, `--si` and `--iec` flags. Defaults to IEC (powers of 1024).
+pub fn get_units(bytes: bool, si: bool) -> Units {
+    if bytes {
+        Units::Bytes
+    } else if si {
+        Units::Si
+    } else {
+        Units::Iec
+    }
+}
+
+/// Parse the `--time` and `--atime` flags. `--atime` shows access times, and implies `--time`.
+pub fn get_time(time: bool, atime: bool) -> Option<TimeKind> {
+    if atime {
+        Some(TimeKind::Accessed)
+    } else if time {
+        Some(TimeKind::Modified)
+    } else {
+        None
+    }
+}
+
+/// Parse the `--older-than`, `--newer-than` and `--atime` flags. Ages are compared against
+/// modification times, or access times with `--atime`.
+pub fn get_age_filter(
+    older_than: Option<&str>,
+    newer_than: Option<&str>,
+    atime: bool,
+) -> Result<AgeFilter, Internal> {
+    Ok(AgeFilter {
+        older_than: get_age(older_than)?,
+        newer_than: get_age(newer_than)?,
+        kind: if atime {
+            TimeKind::Accessed
+        } else {
+            TimeKind::Modified
+        },
+    })
+}
+
+/// Parse the `--older-than` or `--newer-than` flags into a cutoff, given in seconds since the Unix
+/// epoch.
+pub fn get_age(age_from_cli: Option<&str>) -> Result<Option<u64>, Internal> {
+    age_from_cli
+        .map(|a| {
+            let secs = parse_duration(a)?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            Ok(now.saturating_sub(secs))
+        })
+        .transpose()
+}
+
+/// Parse an age such as `90d`, `2w` or `36h` into a number of seconds. The units are `s`, `m`
+/// (or `min`), `h`, `d`, `w`, `mo` (30 days) and `y` (365 days); a bare number is a number of
+/// days.
 ///
 /// # Examples
 ///
 /// ```
 /// use liboskar::prelude::*;
 ///
+/// assert_eq!(parse_duration("90d").ok(), Some(90 * 86400));
+/// assert_eq!(parse_duration("1w").ok(), Some(604800));
+/// assert_eq!(parse_duration("6mo").ok(), Some(6 * 30 * 86400));
+/// assert_eq!(parse_duration("10min").ok(), Some(600));
+/// assert!(parse_duration("3x").is_err());
+/// ```
+pub fn parse_duration(d_from_cli: &str) -> Result<u64, Internal> {
+    let parsed = match get_duration(d_from_cli.as_bytes()) {
+        IResult::Done(_, (n, unit)) => to_secs(n, unit),
+        _ => None,
+    };
+    parsed.ok_or_else(|| Internal::InvalidDuration(d_from_cli.to_string()))
+}
+
+/// Parse a threshold from a command-line flag. Suffixes are read as powers of 1000 for SI units,
+/// and as powers of 1024 otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::prelude::*;
+/// use liboskar::types::Units;
+///
 /// let threshold_string = Some("31M");
-/// assert_eq!(threshold(threshold_string), Some(32505856))
+/// assert_eq!(threshold(threshold_string, Units::Iec).ok(), Some(Some(32505856)));
+/// assert_eq!(threshold(threshold_string, Units::Si).ok(), Some(Some(31000000)));
+/// assert!(threshold(Some("31X"), Units::Iec).is_err());
 /// ```
-pub fn threshold(s: Option<&str>) -> Option<u64> {
-    s.map(pre_threshold)
-}
-
-fn pre_threshold(t_from_cli: &str) -> u64 {
-    match get_threshold(t_from_cli.as_bytes()) {
-        IResult::Done(_, n) => n,
-        _ => {
-            eprintln!(
-                "{}: failed to parse threshold. defaulting to 1M",
-                "Warning".yellow()
-            );
-            1048576 // 1 MB
-        }
-    }
+pub fn threshold(s: Option<&str>, units: Units) -> Result<Option<u64>, Internal> {
+    s.map(|t| parse_threshold(t, units)).transpose()
+}
+
+/// Parse a threshold such as `4096`, `1.5G`, `2T`, `500MiB` or `10kB`. A bare number is a
+/// count of bytes. Single-letter suffixes (`k`, `M`, `G`, `T`, `P`) follow `units`, while `kB`,
+/// `MB`, ... are always powers of 1000 and `KiB`, `MiB`, ... always powers of 1024.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::prelude::*;
+/// use liboskar::types::Units;
+///
+/// assert_eq!(parse_threshold("1.5G", Units::Iec).ok(), Some(1610612736));
+/// assert_eq!(parse_threshold("500MiB", Units::Si).ok(), Some(524288000));
+/// assert_eq!(parse_threshold("2TB", Units::Iec).ok(), Some(2000000000000));
+/// assert_eq!(parse_threshold("4096", Units::Iec).ok(), Some(4096));
+/// assert!(parse_threshold("12X", Units::Iec).is_err());
+/// ```
+pub fn parse_threshold(t_from_cli: &str, units: Units) -> Result<u64, Internal> {
+    let parsed = match get_threshold(t_from_cli.as_bytes()) {
+        IResult::Done(_, (int, frac, suffix)) => to_u64(int, frac, suffix, units),
+        _ => None,
+    };
+    parsed.ok_or_else(|| Internal::InvalidThreshold(t_from_cli.to_string()))
 }
 
-fn to_u64(nums: Vec<char>, size_tag: &[u8]) -> u64 {
-    let pre: String = nums.into_iter().collect();
-    let n = if let Ok(n) = pre.parse::<u64>() {
-        n
+fn to_secs(n: &[u8], unit: Option<&[u8]>) -> Option<u64> {
+    let unit = unit.map(|u| u.to_ascii_lowercase()).unwrap_or_default();
+    let secs = match unit.as_slice() {
+        b"s" => 1,
+        b"m" | b"min" => 60,
+        b"h" => 3600,
+        b"" | b"d" => 86400,
+        b"w" => 7 * 86400,
+        b"mo" => 30 * 86400,
+        b"y" => 365 * 86400,
+        _ => return None,
+    };
+    String::from_utf8_lossy(n)
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(secs)
+}
+
+fn multiplier(suffix: Option<&[u8]>, units: Units) -> Option<u128> {
+    let suffix = suffix.map(|s| s.to_ascii_lowercase()).unwrap_or_default();
+    let (base, power) = match suffix.as_slice() {
+        b"" | b"b" => (1, 0),
+        b"k" => (units.base(), 1),
+        b"m" => (units.base(), 2),
+        b"g" => (units.base(), 3),
+        b"t" => (units.base(), 4),
+        b"p" => (units.base(), 5),
+        b"kb" => (1000, 1),
+        b"mb" => (1000, 2),
+        b"gb" => (1000, 3),
+        b"tb" => (1000, 4),
+        b"pb" => (1000, 5),
+        b"kib" => (1024, 1),
+        b"mib" => (1024, 2),
+        b"gib" => (1024, 3),
+        b"tib" => (1024, 4),
+        b"pib" => (1024, 5),
+        _ => return None,
+    };
+    Some(u128::from(base).pow(power))
+}
+
+fn to_u64(int: &[u8], frac: Option<&[u8]>, suffix: Option<&[u8]>, units: Units) -> Option<u64> {
+    let multiplier = multiplier(suffix, units)?;
+    let int = String::from_utf8_lossy(int).parse::<u128>().ok()?;
+
+    // anything past the 18th decimal place is well below a byte
+    let frac = frac.unwrap_or(b"");
+    let frac = &frac[..frac.len().min(18)];
+    let scale = 10u128.pow(frac.len() as u32);
+    let frac = if frac.is_empty() {
+        0
     } else {
-        eprintln!("{}", Internal::ParseNum);
-        exit(0x0f01);
+        String::from_utf8_lossy(frac).parse::<u128>().ok()?
     };
 
-    match size_tag {
-        b"G" | b"g" => n * 1073741824,
-        b"M" | b"m" => n * 1048576,
-        b"k" | b"K" => n * 1024,
-        b"b" | b"B" => n,
-        _ => exit(0x0f01),
+    let bytes = int.checked_mul(multiplier)? + frac * multiplier / scale;
+    if bytes > u128::from(u64::MAX) {
+        None
+    } else {
+        Some(bytes as u64)
     }
 }
 
-named!(digit_char<&[u8], char>,
-    alt!(
-        char!('1') |
-        char!('2') |
-        char!('3') |
-        char!('4') |
-        char!('5') |
-        char!('6') |
-        char!('7') |
-        char!('8') |
-        char!('9') |
-        char!('0')
+named!(size_suffix<&[u8], &[u8]>,
+    alt_complete!(
+        tag_no_case!("kib") |
+        tag_no_case!("mib") |
+        tag_no_case!("gib") |
+        tag_no_case!("tib") |
+        tag_no_case!("pib") |
+        tag_no_case!("kb") |
+        tag_no_case!("mb") |
+        tag_no_case!("gb") |
+        tag_no_case!("tb") |
+        tag_no_case!("pb") |
+        tag_no_case!("k") |
+        tag_no_case!("m") |
+        tag_no_case!("g") |
+        tag_no_case!("t") |
+        tag_no_case!("p") |
+        tag_no_case!("b")
+    )
+);
+
+named!(get_threshold<&[u8],(&[u8], Option<&[u8]>, Option<&[u8]>)>,
+    do_parse!(
+        int:    digit >>
+        frac:   opt!(complete!(preceded!(char!('.'), digit))) >>
+        suffix: opt!(complete!(size_suffix)) >>
+        eof!() >>
+        ((int, frac, suffix))
+    )
+);
+
+named!(duration_unit<&[u8], &[u8]>,
+    alt_complete!(
+        tag_no_case!("min") |
+        tag_no_case!("mo") |
+        tag_no_case!("s") |
+        tag_no_case!("m") |
+        tag_no_case!("h") |
+        tag_no_case!("d") |
+        tag_no_case!("w") |
+        tag_no_case!("y")
     )
 );
 
-named!(get_threshold<&[u8],u64>,
+named!(get_duration<&[u8],(&[u8], Option<&[u8]>)>,
     do_parse!(
-        nums:     many1!(digit_char) >>
-        size_tag: alt!(tag!("M") |
-                       tag!("G") |
-                       tag!("k") |
-                       tag!("b") |
-                       tag!("B") |
-                       tag!("K") |
-                       tag!("g") |
-                       tag!("m")) >>
-        (to_u64(nums, size_tag))
+        n:    digit >>
+        unit: opt!(complete!(duration_unit)) >>
+        eof!() >>
+        ((n, unit))
     )
 );
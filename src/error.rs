@@ -1,45 +1,215 @@
 use colored::*;
-use regex::Regex;
+use regex;
+use regex::bytes::Regex;
+use std::error::Error;
 use std::fmt;
-use std::process;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+/// Everything that can go wrong reading a directory tree or parsing arguments for it.
 #[derive(Debug)]
 pub enum Internal {
-    ParseNum,
-    ParseIgnore,
+    /// A count, depth or number of threads that isn't a positive whole number.
+    ParseNum(String),
+    /// An ignore file that couldn't be parsed.
+    ParseIgnore(PathBuf),
     GetPath,
-    DirPermissions,
-    NotDirectory,
-    PathDoesNotExist,
-    IoError,
+    /// A directory that can be seen but not read.
+    DirPermissions(PathBuf),
+    /// A path that was expected to be a directory but is something else.
+    NotDirectory(PathBuf),
+    PathDoesNotExist(PathBuf),
+    /// A symlink being followed whose target doesn't exist.
+    BrokenSymlink(PathBuf),
+    /// An entry that is neither a file, a directory nor a symlink, or that couldn't be checked.
+    UnknownFileType(PathBuf),
+    /// Any other failure reading `path`.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A file or directory that `sn clean` couldn't delete.
+    Remove {
+        path: PathBuf,
+        source: io::Error,
+    },
+    InvalidRegex(regex::Error),
+    InvalidThreshold(String),
+    InvalidDuration(String),
+    InvalidFormat(String),
 }
 
 impl fmt::Display for Internal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Internal::IoError => write!(
+            Internal::Io {
+                ref path,
+                ref source,
+            } => write!(f, "could not read '{}': {}", path.display(), source),
+            Internal::Remove {
+                ref path,
+                ref source,
+            } => write!(f, "could not remove '{}': {}", path.display(), source),
+            Internal::PathDoesNotExist(ref p) => write!(
                 f,
-                "{}: IO error when trying to read directory contents",
-                "Error".red()
+                "path '{}' does not exist, or you do not have permission to enter.",
+                p.display()
             ),
-            Internal::ParseNum => write!(
+            Internal::DirPermissions(ref p) => {
+                write!(f, "permission denied for directory: {}", p.display())
+            }
+            Internal::BrokenSymlink(ref p) => write!(f, "broken symlink: {}", p.display()),
+            Internal::UnknownFileType(ref p) => {
+                write!(f, "could not determine file type for: {}", p.display())
+            }
+            Internal::NotDirectory(ref p) => write!(f, "{} is not a directory.", p.display()),
+            Internal::ParseNum(ref n) => write!(
                 f,
-                "{}: Please enter a positive whole number.",
-                "Error".red()
+                "could not parse '{}'. Please enter a positive whole number.",
+                n
             ),
-            _ => write!(f, "other error"),
+            Internal::InvalidRegex(ref e) => write!(f, "Invalid regex:\n    {}", e),
+            Internal::InvalidThreshold(ref t) => write!(
+                f,
+                "could not parse threshold '{}'. Expected a number of bytes with an optional unit, e.g. 4096, 1.5G, 2T or 500MiB.",
+                t
+            ),
+            Internal::InvalidDuration(ref t) => write!(
+                f,
+                "could not parse age '{}'. Expected a number with a unit, e.g. 36h, 90d, 2w, 6mo or 1y.",
+                t
+            ),
+            Internal::InvalidFormat(ref s) => write!(f, "unrecognized output format '{}'", s),
+            Internal::ParseIgnore(ref p) => {
+                write!(f, "failed to parse ignore file at {}, ignoring", p.display())
+            }
+            Internal::GetPath => write!(f, "could not determine path"),
+        }
+    }
+}
+
+impl Error for Internal {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            Internal::Io { ref source, .. } | Internal::Remove { ref source, .. } => Some(source),
+            Internal::InvalidRegex(ref e) => Some(e),
+            _ => None,
         }
     }
 }
 
-/// Check that the user-supplied regex is valid. If not, fail gracefully.
-pub fn check_regex(re: &str) -> Regex {
-    if let Ok(r) = Regex::new(re) {
-        r
-    } else if let Err(x) = Regex::new(re) {
-        eprintln!("{}: Invalid regex:\n    {}", "Error".red(), x);
-        process::exit(0x0f01)
-    } else {
-        process::exit(0x0f01)
+impl Internal {
+    /// Why `path` couldn't be read. Permissions and missing paths have their own variants; any
+    /// other failure keeps the `io::Error`.
+    pub fn from_io(path: &Path, source: io::Error) -> Internal {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Internal::DirPermissions(path.to_path_buf()),
+            io::ErrorKind::NotFound => Internal::PathDoesNotExist(path.to_path_buf()),
+            _ => Internal::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+}
+
+/// Check that the user-supplied regex is valid.
+pub fn check_regex(re: &str) -> Result<Regex, Internal> {
+    Regex::new(re).map_err(Internal::InvalidRegex)
+}
+
+/// Counts of the problems met during a scan that didn't stop it, so that they can be summarized
+/// once it's done.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    unreadable_dirs: AtomicUsize,
+    not_found: AtomicUsize,
+    broken_symlinks: AtomicUsize,
+    other: AtomicUsize,
+    quiet: bool,
+}
+
+impl Warnings {
+    pub fn new() -> Warnings {
+        Warnings::default()
+    }
+
+    /// Count problems without printing any warnings.
+    pub fn quiet() -> Warnings {
+        Warnings {
+            quiet: true,
+            ..Warnings::default()
+        }
+    }
+
+    /// Whether warnings are being left out.
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Count a problem and print it as a warning.
+    pub fn warn(&self, e: &Internal) {
+        self.count(e);
+        self.note(e);
+    }
+
+    /// Print a warning that isn't about anything being skipped.
+    pub fn note<D: fmt::Display>(&self, message: D) {
+        if !self.quiet {
+            eprintln!("{}: {}", "Warning".yellow(), message);
+        }
+    }
+
+    /// Count a problem without printing anything.
+    pub fn count(&self, e: &Internal) {
+        let counter = match *e {
+            Internal::DirPermissions(_) => &self.unreadable_dirs,
+            Internal::PathDoesNotExist(_) => &self.not_found,
+            Internal::BrokenSymlink(_) => &self.broken_symlinks,
+            _ => &self.other,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether anything was skipped.
+    pub fn is_empty(&self) -> bool {
+        self.summary().is_none()
+    }
+
+    /// What was skipped, e.g. "37 directories unreadable (permission denied), 2 broken
+    /// symlinks".
+    pub fn summary(&self) -> Option<String> {
+        let groups = [
+            (
+                &self.unreadable_dirs,
+                "directory",
+                "directories",
+                " unreadable (permission denied)",
+            ),
+            (&self.not_found, "path", "paths", " not found"),
+            (
+                &self.broken_symlinks,
+                "broken symlink",
+                "broken symlinks",
+                "",
+            ),
+            (&self.other, "unreadable entry", "unreadable entries", ""),
+        ];
+        let parts: Vec<String> = groups
+            .iter()
+            .filter_map(
+                |&(counter, one, many, why)| match counter.load(Ordering::Relaxed) {
+                    0 => None,
+                    1 => Some(format!("1 {}{}", one, why)),
+                    n => Some(format!("{} {}{}", n, many, why)),
+                },
+            )
+            .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
     }
 }
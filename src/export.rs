@@ -0,0 +1,87 @@
+//! Export a scan in the [ncdu](https://dev.yorhel.nl/ncdu/jsonfmt) JSON format, so that it can
+//! be browsed with `ncdu -f`.
+
+use cache::FileInfo;
+use output::json_string;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::{FileTree, NamePair};
+
+// ncdu adds up the sizes of a directory's contents itself, so each entry is written with only its
+// own sizes, taken from its metadata. Files with several hard links are marked as such, so that
+// ncdu can count each of them once.
+fn write_info<W: Write>(
+    out: &mut W,
+    name: &str,
+    info: Option<FileInfo>,
+    is_dir: bool,
+) -> io::Result<()> {
+    write!(out, "{{\"name\":{}", json_string(name))?;
+    if let Some(info) = info {
+        write!(
+            out,
+            ",\"asize\":{},\"dsize\":{},\"ino\":{}",
+            info.len, info.blocks, info.ino
+        )?;
+        if !is_dir && info.nlink > 1 {
+            write!(out, ",\"hlnkc\":true")?;
+        }
+    }
+    write!(out, "}}")
+}
+
+fn write_dir<W: Write>(
+    out: &mut W,
+    name: &str,
+    info: Option<FileInfo>,
+    children: &[NamePair],
+) -> io::Result<()> {
+    write!(out, "[")?;
+    write_info(out, name, info, true)?;
+    for child in children {
+        writeln!(out, ",")?;
+        write_entry(out, child)?;
+    }
+    write!(out, "]")
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &NamePair) -> io::Result<()> {
+    let name = entry
+        .name
+        .file_name()
+        .unwrap_or_else(|| entry.name.as_os_str())
+        .to_string_lossy();
+    if entry.is_dir() {
+        write_dir(out, &name, entry.info, entry.children())
+    } else {
+        write_info(out, &name, entry.info, false)
+    }
+}
+
+/// Write a scan of `root` in the ncdu JSON export format. The tree should be read in full, i.e.
+/// without a maximum depth or threshold, and with every hard link counted; ncdu keeps apparent
+/// sizes and disk usage apart and counts hard links itself, so both sizes and the inode of each
+/// entry are written whichever sizes the scan added up.
+pub fn write_ncdu<W: Write>(tree: &FileTree, root: &Path, out: &mut W) -> io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    writeln!(
+        out,
+        "[1,0,{{\"progname\":\"sn\",\"progver\":\"{}\",\"timestamp\":{}}},",
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )?;
+    let name = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let name = name.to_string_lossy();
+    if root.is_dir() {
+        write_dir(out, &name, tree.info, tree.children())?;
+    } else {
+        write_info(out, &name, tree.info, false)?;
+    }
+    writeln!(out, "]")
+}
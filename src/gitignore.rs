@@ -1,77 +1,39 @@
-use colored::*;
+use error::Internal;
 use nom::IResult;
-use regex::RegexSet;
-use std::path::PathBuf;
+use regex::bytes::RegexSet;
+use std::path::Path;
 
 /// Given a darcs boring file's contents, process it as a `RegexSet`. The second
-/// argument is a file path, included so that we print nice errors.
-pub fn darcs_contents_to_regex(file: &str, file_path: &PathBuf) -> RegexSet {
-    let processed_vec: Vec<&str> = process_darcs_full(file, file_path);
+/// argument is a file path, included so that we return nice errors.
+pub fn darcs_contents_to_regex(file: &str, file_path: &Path) -> Result<RegexSet, Internal> {
+    let processed_vec: Vec<&str> = process_darcs_full(file, file_path)?;
     let processed_str: String = processed_vec.join("");
     let lines = processed_str.split_whitespace();
 
-    let maybe_set = RegexSet::new(lines);
-    if let Ok(s) = maybe_set {
-        s
-    } else {
-        eprintln!(
-            "{}: failed to parse darcs boring file at {:?}, ignoring",
-            "Warning".yellow(),
-            file_path
-        );
-        let empty: Vec<&str> = Vec::new();
-        RegexSet::new(empty).expect("Error creating regex from empty vector")
-    }
+    RegexSet::new(lines).map_err(|_| Internal::ParseIgnore(file_path.to_path_buf()))
 }
 
 /// Given a `.gitignore` or `.ignore` file's contents, process it as a `RegexSet`. The second
-/// argument is a file path, included so that we print nice errors.
-pub fn file_contents_to_regex(file: &str, file_path: &PathBuf) -> RegexSet {
-    let processed_vec: Vec<&str> = process_to_vector(file, file_path);
+/// argument is a file path, included so that we return nice errors.
+pub fn file_contents_to_regex(file: &str, file_path: &Path) -> Result<RegexSet, Internal> {
+    let processed_vec: Vec<&str> = process_to_vector(file, file_path)?;
     let processed_str: String = processed_vec.join("");
     let lines = processed_str.split_whitespace();
 
-    let maybe_set = RegexSet::new(lines.clone());
-    if let Ok(s) = maybe_set {
-        s
-    } else {
-        println!("{:?}", lines.collect::<Vec<&str>>());
-        println!("{:?}", maybe_set);
-        eprintln!(
-            "{}: failed to parse .gitignore/.ignore at {:?}, ignoring",
-            "Warning".yellow(),
-            file_path
-        );
-        let empty: Vec<&str> = Vec::new();
-        RegexSet::new(empty).expect("Error creating regex from empty vector")
-    }
+    RegexSet::new(lines).map_err(|_| Internal::ParseIgnore(file_path.to_path_buf()))
 }
 
-fn process_to_vector<'a>(input: &'a str, file_path: &PathBuf) -> Vec<&'a str> {
+fn process_to_vector<'a>(input: &'a str, file_path: &Path) -> Result<Vec<&'a str>, Internal> {
     match process(input) {
-        IResult::Done(_, result) => result,
-        _ => {
-            eprintln!(
-                "{}: Failed to parse gitignore at: {}",
-                "Error".red(),
-                file_path.display()
-            );
-            Vec::new()
-        }
+        IResult::Done(_, result) => Ok(result),
+        _ => Err(Internal::ParseIgnore(file_path.to_path_buf())),
     }
 }
 
-fn process_darcs_full<'a>(input: &'a str, file_path: &PathBuf) -> Vec<&'a str> {
+fn process_darcs_full<'a>(input: &'a str, file_path: &Path) -> Result<Vec<&'a str>, Internal> {
     match process_darcs(input) {
-        IResult::Done(_, result) => result,
-        _ => {
-            eprintln!(
-                "{}: Failed to parse darcs boring file at: {}",
-                "Error".red(),
-                file_path.display()
-            );
-            Vec::new()
-        }
+        IResult::Done(_, result) => Ok(result),
+        _ => Err(Internal::ParseIgnore(file_path.to_path_buf())),
     }
 }
 
@@ -14,10 +14,19 @@ extern crate lazy_static;
 
 extern crate colored;
 extern crate regex;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
+pub mod cache;
 pub mod cli_helpers;
 pub mod error;
+pub mod export;
 pub mod gitignore;
+pub mod output;
+pub mod scan;
+pub mod snapshot;
 #[cfg(test)]
 pub mod test;
 pub mod types;
@@ -28,6 +37,8 @@ pub mod prelude {
 
     pub use cli_helpers::*;
     pub use error::*;
+    pub use output::*;
+    pub use scan::*;
     pub use utils::*;
     pub use walk_parallel::*;
 }
@@ -4,12 +4,21 @@ extern crate clap;
 extern crate colored;
 extern crate liboskar;
 
-use clap::{App, AppSettings};
+use clap::{App, AppSettings, ArgMatches};
 use colored::*;
+use liboskar::cache::{save_cache, DirCache};
+use liboskar::export::*;
 use liboskar::prelude::*;
+use liboskar::snapshot::*;
+use liboskar::types::{StreamPrinter, TopN};
+use std::cell::Cell;
 use std::env;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{exit, Command};
+use std::sync::Arc;
 
 #[allow(unknown_lints)]
 #[allow(clippy::cognitive_complexity)]
@@ -28,6 +37,22 @@ fn main() {
         .setting(AppSettings::SubcommandRequired)
         .get_matches();
 
+    // problems that don't stop a scan, summarized at the end unless warnings are silenced
+    let warnings = Arc::new(if silent(&matches) {
+        Warnings::quiet()
+    } else {
+        Warnings::new()
+    });
+
+    // directories that couldn't be read at all, to fail if none of those asked for could be
+    let failed = Cell::new(0);
+    // print an error that stopped a directory being read, counting it towards the summary
+    let report = |e: &Internal| {
+        failed.set(failed.get() + 1);
+        warnings.count(e);
+        eprintln!("{}: {}", "Error".red(), e);
+    };
+
     // TODO this should install manpages?
     if let Some(x) = matches.subcommand_matches("update") {
         let force = x.is_present("force");
@@ -58,7 +83,7 @@ fn main() {
 
         let home_dir = PathBuf::from(home_dir_str);
 
-        let regex = command.value_of("excludes").map(|r| check_regex(r));
+        let regex = command.value_of("excludes").map(check_regex).map(or_exit);
 
         // whether to clean up tagfiles generated for vim/emacs
         let vimtags = command.is_present("tags");
@@ -70,16 +95,161 @@ fn main() {
 
         for dir in dirs {
             if (dir != home_dir) && !force {
-                clean_project_dirs(dir, &regex, vimtags);
+                if let Err(e) = clean_project_dirs(dir, &regex, vimtags, &warnings) {
+                    report(&e);
+                }
             } else {
-                eprintln!(
-                    "{}: not cleaning directory '{}', as it is your home directory. To clean your home directory, rerun with --force.",
-                    "Warning".yellow(),
+                warnings.note(format!(
+                    "not cleaning directory '{}', as it is your home directory. To clean your home directory, rerun with --force.",
                     dir.display()
-                )
+                ))
             }
         }
     }
+    // export a full scan
+    else if let Some(command) = matches.subcommand_matches("export") {
+        let regex = command.value_of("excludes").map(check_regex).map(or_exit);
+
+        // set path to dir
+        let dir = get_dir(command.value_of("dir"));
+
+        // get the number of processors to be used
+        let nproc = or_exit(get_threads(command.value_of("threads")));
+
+        // every hard link is kept, for ncdu to count each file once itself
+        let opts = ScanOptions::new(dir.clone())
+            .warnings(warnings.clone())
+            .excludes(regex)
+            .count_links(true)
+            .follow_symlinks(command.is_present("follow-symlinks"))
+            .one_file_system(command.is_present("one-file-system"))
+            .threads(nproc);
+        let tree = match scan(&opts) {
+            Ok(t) => t,
+            Err(e) => {
+                report(&e);
+                exit(0x0001)
+            }
+        };
+
+        // ok because it's a required argument
+        let out_path = command.value_of("ncdu").unwrap();
+        let result = if out_path == "-" {
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            write_ncdu(&tree, &dir, &mut out).and_then(|_| out.flush())
+        } else {
+            File::create(out_path).and_then(|f| {
+                let mut out = BufWriter::new(f);
+                write_ncdu(&tree, &dir, &mut out).and_then(|_| out.flush())
+            })
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}: could not write '{}': {}", "Error".red(), out_path, e);
+            exit(0x0001)
+        }
+    }
+    // save a scan, or compare it with a later one
+    else if let Some(command) = matches.subcommand_matches("snapshot") {
+        // read the full tree with raw sizes, so that any two scans can be compared
+        let scan_dir = |command: &clap::ArgMatches, dir: &PathBuf| {
+            let opts = ScanOptions::new(dir.clone())
+                .warnings(warnings.clone())
+                .excludes(command.value_of("excludes").map(check_regex).map(or_exit))
+                .blocks(command.is_present("disk-usage"))
+                .count_links(command.is_present("count-links"))
+                .follow_symlinks(command.is_present("follow-symlinks"))
+                .one_file_system(command.is_present("one-file-system"))
+                .threads(or_exit(get_threads(command.value_of("threads"))))
+                .cache(command.value_of("cache").map(PathBuf::from));
+            match scan(&opts) {
+                Ok(t) => t,
+                Err(e) => {
+                    report(&e);
+                    exit(0x0001)
+                }
+            }
+        };
+
+        // ok to unwrap the arguments below because they're required
+        if let Some(command) = command.subcommand_matches("save") {
+            let dir = get_dir(command.value_of("dir"));
+            let tree = scan_dir(command, &dir);
+            let blocks = command.is_present("disk-usage");
+
+            let out_path = command.value_of("output").unwrap();
+            let result = if out_path == "-" {
+                let stdout = io::stdout();
+                let mut out = BufWriter::new(stdout.lock());
+                write_snapshot(&tree, &dir, blocks, &mut out).and_then(|_| out.flush())
+            } else {
+                File::create(out_path).and_then(|f| {
+                    let mut out = BufWriter::new(f);
+                    write_snapshot(&tree, &dir, blocks, &mut out).and_then(|_| out.flush())
+                })
+            };
+
+            if let Err(e) = result {
+                eprintln!("{}: could not write '{}': {}", "Error".red(), out_path, e);
+                exit(0x0001)
+            }
+        } else if let Some(command) = command.subcommand_matches("diff") {
+            let units = get_units(command.is_present("bytes"), command.is_present("si"));
+            let min_bytes = or_exit(threshold(command.value_of("threshold"), units)).unwrap_or(0);
+
+            let read = |path: &str| match File::open(path).and_then(read_snapshot) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("{}: could not read '{}': {}", "Error".red(), path, e);
+                    exit(0x0001)
+                }
+            };
+
+            let old_path = command.value_of("old").unwrap();
+            let old = read(old_path);
+            let new_path = command.value_of("new").unwrap();
+            let new = if PathBuf::from(new_path).is_dir() {
+                let dir = get_dir(Some(new_path));
+                let tree = scan_dir(command, &dir);
+                Snapshot {
+                    dir,
+                    tree,
+                    blocks: command.is_present("disk-usage"),
+                }
+            } else {
+                read(new_path)
+            };
+
+            // disk usage and apparent sizes differ for nearly every directory
+            if old.blocks != new.blocks {
+                let sizes = |blocks| {
+                    if blocks {
+                        "disk usage"
+                    } else {
+                        "apparent sizes"
+                    }
+                };
+                eprintln!(
+                    "{}: '{}' has {} but '{}' has {}; both must use the same sizes (see --disk-usage)",
+                    "Error".red(),
+                    old_path,
+                    sizes(old.blocks),
+                    new_path,
+                    sizes(new.blocks)
+                );
+                exit(0x0001)
+            }
+
+            let mut changes = diff(&old.tree, &old.dir, &new.tree, &new.dir);
+            changes.retain(|c| c.delta().unsigned_abs() >= u128::from(min_bytes));
+            if let Some(n) = command.value_of("count") {
+                changes.truncate(or_exit(get_num(Some(n))));
+            }
+
+            display_changes(&changes, &new.dir, units);
+        }
+    }
     // test stuff
     else if let Some(command) = matches.subcommand_matches("parallel") {
         // set flag to print everything
@@ -89,101 +259,214 @@ fn main() {
         let print_files = command.is_present("files");
 
         // get the number of processors to be used
-        let nproc = get_threads(command.value_of("threads"));
+        let nproc = or_exit(get_threads(command.value_of("threads")));
+
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
 
         // set threshold
-        let min_bytes = threshold(command.value_of("threshold"));
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units));
+
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
 
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
-        let regex = command.value_of("excludes").map(|r| check_regex(r));
+        let regex = command.value_of("excludes").map(check_regex).map(or_exit);
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command
+            .value_of("cache")
+            .map(|p| Arc::new(DirCache::open(p, &warnings)));
+        let roots = dirs.clone();
 
         for dir in dirs {
-            let mut w = Walk::new(dir, nproc);
-            if let Some(b) = min_bytes {
-                w.set_threshold(b);
-            }
-            if !print_all {
-                let depth = get_depth(command.value_of("depth"));
-                w.set_depth(depth);
-            } else if command.is_present("depth") {
-                eprintln!(
-                    "{}: flag --all is not compatible with --depth",
-                    "Warning".yellow()
-                );
+            let depth = if !print_all {
+                Some(or_exit(get_depth(command.value_of("depth"))))
+            } else {
+                if command.is_present("depth") {
+                    warnings.note("flag --all is not compatible with --depth");
+                }
+                None
+            };
+            let mut w = ScanOptions::new(dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .threshold(min_bytes)
+                .files(print_files)
+                .excludes(regex.clone())
+                .blocks(blocks)
+                .count_links(command.is_present("count-links"))
+                .follow_symlinks(command.is_present("follow-symlinks"))
+                .one_file_system(command.is_present("one-file-system"))
+                .age(or_exit(get_age_filter(
+                    command.value_of("older-than"),
+                    command.value_of("newer-than"),
+                    command.is_present("atime"),
+                )))
+                .threads(nproc)
+                .walk();
+            if let Some(ref c) = cache {
+                w.set_cache(c.clone());
             }
-            if print_files {
-                w.with_files();
+            w.set_format(format);
+            w.set_units(units);
+            if command.is_present("count-entries") {
+                w.count_entries();
             }
+            w.set_time(get_time(
+                command.is_present("time"),
+                command.is_present("atime"),
+            ));
 
-            if let Some(e) = regex.clone() {
-                w.set_regex(e);
+            if let Err(e) = print_parallel(w) {
+                report(&e);
             }
-
-            print_parallel(w);
         }
+
+        save_cache(cache.as_deref(), &roots, &warnings);
     }
     // find large files
     else if let Some(command) = matches.subcommand_matches("fat") {
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
+
+        // follow symlinks, reading each target once
+        let follow = command.is_present("follow-symlinks");
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command.value_of("cache").map(PathBuf::from);
+
+        // count each file once, however many hard links it has
+        let count_links = command.is_present("count-links");
+
+        // don't descend into other filesystems
+        let one_fs = command.is_present("one-file-system");
+
         // set threshold
-        let min_bytes = match threshold(command.value_of("threshold")) {
-            Some(t) => t,
-            _ => 31457280, // 30 MB
-        };
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units))
+            .unwrap_or(30 * units.base() * units.base()); // 30 MB
 
         // set depth
         let depth = if !command.is_present("all") {
-            Some(get_depth(command.value_of("depth")))
+            Some(or_exit(get_depth(command.value_of("depth"))))
         } else if command.is_present("depth") {
-            eprintln!(
-                "{}: flag --all is not compatible with --depth",
-                "Warning".yellow()
-            );
+            warnings.note("flag --all is not compatible with --depth");
             None
         } else {
             None
         };
 
-        // don't print warnings
-        //let silent = command.is_present("silent");
-
         // set whether to print files too
         let print_files = command.is_present("files");
 
         // set regex for exclusions
         let regex = command.value_of("excludes");
 
+        // set whether to show results as a tree
+        let tree = command.is_present("tree");
+
+        // whether to print entries as soon as they are read
+        let stream = command.is_present("stream");
+
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
+        let opts = DisplayOptions {
+            format,
+            bars: command.is_present("bars"),
+            counts: false,
+            units,
+            time: get_time(command.is_present("time"), command.is_present("atime")),
+        };
+
+        // set age filters
+        let age = or_exit(get_age_filter(
+            command.value_of("older-than"),
+            command.value_of("newer-than"),
+            command.is_present("atime"),
+        ));
+
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
         for dir in dirs {
-            // get relevant filenames &c.
-            let v = match regex {
-                Some(r) => read_all(&dir, 0, depth, Some(&check_regex(r)), &None, false, false),
-                _ => read_all(&dir, 0, depth, None, &None, false, false),
-            };
-
-            // filter by depth
-            let mut v_filtered = v.filtered(Some(min_bytes), !print_files, depth);
-
-            // display results
-            v_filtered.display_tree(&dir);
+            let scan_opts = ScanOptions::new(&dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .excludes(regex.map(check_regex).map(or_exit))
+                .blocks(blocks)
+                .count_links(count_links)
+                .follow_symlinks(follow)
+                .one_file_system(one_fs)
+                .cache(cache.clone());
+            if stream {
+                let mut printer =
+                    StreamPrinter::new(&dir, &opts, Some(min_bytes), !print_files, depth, age);
+                match scan_each(&scan_opts, &mut |e| printer.print(&e)) {
+                    Ok(totals) => printer.finish(&dir, totals),
+                    Err(e) => report(&e),
+                }
+            } else {
+                // get relevant filenames &c., filtered by depth, size and age
+                let scan_opts = scan_opts.threshold(min_bytes).files(print_files).age(age);
+                let mut v_filtered = match scan(&scan_opts) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        report(&e);
+                        continue;
+                    }
+                };
+
+                // display results
+                if tree && format == OutputFormat::Human {
+                    v_filtered.display_indented(&dir, units);
+                } else {
+                    v_filtered.display(&dir, &opts);
+                }
+            }
         }
     }
     // find large files
     else if let Some(command) = matches.subcommand_matches("directories") {
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
+
+        // follow symlinks, reading each target once
+        let follow = command.is_present("follow-symlinks");
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command.value_of("cache").map(PathBuf::from);
+
+        // count each file once, however many hard links it has
+        let count_links = command.is_present("count-links");
+
+        // don't descend into other filesystems
+        let one_fs = command.is_present("one-file-system");
+
         // set threshold
-        let min_bytes = threshold(command.value_of("threshold"));
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units));
 
         // set depth
         let depth = if !command.is_present("all") {
-            Some(get_depth(command.value_of("depth")))
+            Some(or_exit(get_depth(command.value_of("depth"))))
         } else if command.is_present("depth") {
-            eprintln!(
-                "{}: flag --all is not compatible with --depth",
-                "Warning".yellow()
-            );
+            warnings.note("flag --all is not compatible with --depth");
             None
         } else {
             None
@@ -195,34 +478,120 @@ fn main() {
         // set whether to print files too
         let print_files = command.is_present("files");
 
+        // set whether to show results as a tree
+        let tree = command.is_present("tree");
+
+        // whether to print entries as soon as they are read
+        let stream = command.is_present("stream");
+
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
+        let opts = DisplayOptions {
+            format,
+            bars: command.is_present("bars"),
+            counts: command.is_present("count-entries"),
+            units,
+            time: get_time(command.is_present("time"), command.is_present("atime")),
+        };
+
+        // set age filters
+        let age = or_exit(get_age_filter(
+            command.value_of("older-than"),
+            command.value_of("newer-than"),
+            command.is_present("atime"),
+        ));
+
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
         for dir in dirs {
-            // get relevant filenames &c.
-            let v = match regex {
-                Some(r) => read_all(&dir, 0, depth, Some(&check_regex(r)), &None, false, false),
-                _ => read_all_fast(&dir, 0, depth),
-            };
-
-            // filter by depth
-            let mut v_filtered = v.filtered(min_bytes, !print_files, depth);
-
-            // display results
-            v_filtered.display_tree(&dir);
+            // stay on the filesystem the directory is on
+            let device = if one_fs { device(&dir) } else { None };
+            if command.is_present("list-symlinks") {
+                let excludes = regex.map(check_regex).map(or_exit);
+                let listed = broken_symlinks(
+                    &dir,
+                    excludes.as_ref(),
+                    device,
+                    &warnings,
+                    &mut |path, target| {
+                        println!(
+                            "{} -> {}",
+                            path.display(),
+                            target.display().to_string().red()
+                        )
+                    },
+                );
+                if let Err(e) = listed {
+                    report(&e);
+                }
+                continue;
+            }
+            let scan_opts = ScanOptions::new(&dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .excludes(regex.map(check_regex).map(or_exit))
+                .blocks(blocks)
+                .count_links(count_links)
+                .follow_symlinks(follow)
+                .one_file_system(one_fs)
+                .cache(cache.clone());
+            if stream {
+                let mut printer =
+                    StreamPrinter::new(&dir, &opts, min_bytes, !print_files, depth, age);
+                match scan_each(&scan_opts, &mut |e| printer.print(&e)) {
+                    Ok(totals) => printer.finish(&dir, totals),
+                    Err(e) => report(&e),
+                }
+            } else {
+                // get relevant filenames &c., filtered by depth, size and age
+                let scan_opts = scan_opts.threshold(min_bytes).files(print_files).age(age);
+                let mut v_filtered = match scan(&scan_opts) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        report(&e);
+                        continue;
+                    }
+                };
+
+                // display results
+                if tree && format == OutputFormat::Human {
+                    v_filtered.display_indented(&dir, units);
+                } else {
+                    v_filtered.display(&dir, &opts);
+                }
+            }
         }
     } else if let Some(command) = matches.subcommand_matches("files") {
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
+
+        // follow symlinks, reading each target once
+        let follow = command.is_present("follow-symlinks");
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command.value_of("cache").map(PathBuf::from);
+
+        // count each file once, however many hard links it has
+        let count_links = command.is_present("count-links");
+
+        // don't descend into other filesystems
+        let one_fs = command.is_present("one-file-system");
+
         // set threshold
-        let min_bytes = threshold(command.value_of("threshold"));
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units));
 
         // set depth
         let depth = if !command.is_present("all") {
-            Some(get_depth(command.value_of("depth")))
+            Some(or_exit(get_depth(command.value_of("depth"))))
         } else if command.is_present("depth") {
-            eprintln!(
-                "{}: flag --all is not compatible with --depth",
-                "Warning".yellow()
-            );
+            warnings.note("flag --all is not compatible with --depth");
             None
         } else {
             None
@@ -234,34 +603,113 @@ fn main() {
         // set whether to print files too
         let print_files = true;
 
+        // whether to print entries as soon as they are read
+        let stream = command.is_present("stream");
+
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
+        let opts = DisplayOptions {
+            format,
+            bars: command.is_present("bars"),
+            counts: command.is_present("count-entries"),
+            units,
+            time: get_time(command.is_present("time"), command.is_present("atime")),
+        };
+
+        // set age filters
+        let age = or_exit(get_age_filter(
+            command.value_of("older-than"),
+            command.value_of("newer-than"),
+            command.is_present("atime"),
+        ));
+
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
         for dir in dirs {
-            // get relevant filenames &c.
-            let v = match regex {
-                Some(r) => read_all(&dir, 0, depth, Some(&check_regex(r)), &None, false, false),
-                _ => read_all(&dir, 0, depth, None, &None, false, false),
-            };
-
-            // filter by depth
-            let mut v_filtered = v.filtered(min_bytes, !print_files, depth);
-
-            // display results
-            v_filtered.display_tree(&dir);
+            // stay on the filesystem the directory is on
+            let device = if one_fs { device(&dir) } else { None };
+            if command.is_present("list-symlinks") {
+                let excludes = regex.map(check_regex).map(or_exit);
+                let listed = broken_symlinks(
+                    &dir,
+                    excludes.as_ref(),
+                    device,
+                    &warnings,
+                    &mut |path, target| {
+                        println!(
+                            "{} -> {}",
+                            path.display(),
+                            target.display().to_string().red()
+                        )
+                    },
+                );
+                if let Err(e) = listed {
+                    report(&e);
+                }
+                continue;
+            }
+            let scan_opts = ScanOptions::new(&dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .excludes(regex.map(check_regex).map(or_exit))
+                .blocks(blocks)
+                .count_links(count_links)
+                .follow_symlinks(follow)
+                .one_file_system(one_fs)
+                .cache(cache.clone());
+            if stream {
+                let mut printer =
+                    StreamPrinter::new(&dir, &opts, min_bytes, !print_files, depth, age);
+                match scan_each(&scan_opts, &mut |e| printer.print(&e)) {
+                    Ok(totals) => printer.finish(&dir, totals),
+                    Err(e) => report(&e),
+                }
+            } else {
+                // get relevant filenames &c., filtered by depth, size and age
+                let scan_opts = scan_opts.threshold(min_bytes).files(print_files).age(age);
+                let mut v_filtered = match scan(&scan_opts) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        report(&e);
+                        continue;
+                    }
+                };
+
+                // display results
+                v_filtered.display(&dir, &opts);
+            }
         }
     } else if let Some(command) = matches.subcommand_matches("artifacts") {
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
+
+        // follow symlinks, reading each target once
+        let follow = command.is_present("follow-symlinks");
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command.value_of("cache").map(PathBuf::from);
+
+        // count each file once, however many hard links it has
+        let count_links = command.is_present("count-links");
+
+        // don't descend into other filesystems
+        let one_fs = command.is_present("one-file-system");
+
         // set threshold
-        let min_bytes = threshold(command.value_of("threshold"));
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units));
 
         // set depth
         let depth = if !command.is_present("all") {
-            Some(get_depth(command.value_of("depth")))
+            Some(or_exit(get_depth(command.value_of("depth"))))
         } else if command.is_present("depth") {
-            eprintln!(
-                "{}: flag --all is not compatible with --depth",
-                "Warning".yellow()
-            );
+            warnings.note("flag --all is not compatible with --depth");
             None
         } else {
             None
@@ -269,7 +717,7 @@ fn main() {
 
         // set number to print out
         let num_int = if command.is_present("count") {
-            Some(get_num(command.value_of("count")))
+            Some(or_exit(get_num(command.value_of("count"))))
         } else {
             None
         };
@@ -283,36 +731,104 @@ fn main() {
         // set whether to print files too
         let print_files = command.is_present("files");
 
+        // whether to print entries as soon as they are read
+        let stream = command.is_present("stream");
+
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
+        let opts = DisplayOptions {
+            format,
+            bars: command.is_present("bars"),
+            counts: false,
+            units,
+            time: get_time(command.is_present("time"), command.is_present("atime")),
+        };
+
+        // set age filters
+        let age = or_exit(get_age_filter(
+            command.value_of("older-than"),
+            command.value_of("newer-than"),
+            command.is_present("atime"),
+        ));
+
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
         for dir in dirs {
-            // get relevant filenames &c.
-            let excludes = get_excludes(command.value_of("excludes"));
-            let v = read_all(&dir, 0, depth, Some(&excludes), &None, vimtags, true);
-
-            let mut v_processed = if should_sort {
-                v.sort(num_int, min_bytes, !print_files, depth)
+            let scan_opts = ScanOptions::new(&dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .excludes(or_exit(get_excludes(command.value_of("excludes"))))
+                .vimtags(vimtags)
+                .artifacts_only(true)
+                .blocks(blocks)
+                .count_links(count_links)
+                .follow_symlinks(follow)
+                .one_file_system(one_fs)
+                .cache(cache.clone());
+            if stream {
+                let mut printer =
+                    StreamPrinter::new(&dir, &opts, min_bytes, !print_files, depth, age);
+                match scan_each(&scan_opts, &mut |e| printer.print(&e)) {
+                    Ok(totals) => printer.finish(&dir, totals),
+                    Err(e) => report(&e),
+                }
             } else {
-                v.filtered(min_bytes, !print_files, depth)
-            };
-
-            v_processed.display_tree(&dir);
+                // get relevant filenames &c.
+                let scan_opts = if should_sort {
+                    scan_opts.age(age)
+                } else {
+                    scan_opts.threshold(min_bytes).files(print_files).age(age)
+                };
+                let v = match scan(&scan_opts) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        report(&e);
+                        continue;
+                    }
+                };
+
+                let mut v_processed = if should_sort {
+                    v.sort(num_int, min_bytes, !print_files, depth)
+                } else {
+                    v
+                };
+
+                v_processed.display(&dir, &opts);
+            }
         }
     }
     // sort entities by size
     else if let Some(command) = matches.subcommand_matches("sort") {
+        // set units for sizes and thresholds
+        let units = get_units(command.is_present("bytes"), command.is_present("si"));
+
+        // count allocated blocks rather than apparent sizes
+        let blocks = command.is_present("disk-usage");
+
+        // follow symlinks, reading each target once
+        let follow = command.is_present("follow-symlinks");
+
+        // reuse the listings of directories that haven't changed since the last scan
+        let cache = command.value_of("cache").map(PathBuf::from);
+
+        // count each file once, however many hard links it has
+        let count_links = command.is_present("count-links");
+
+        // don't descend into other filesystems
+        let one_fs = command.is_present("one-file-system");
+
         // set threshold
-        let min_bytes = threshold(command.value_of("threshold"));
+        let min_bytes = or_exit(threshold(command.value_of("threshold"), units));
 
         // set depth
         let depth = if !command.is_present("all") {
-            Some(get_depth(command.value_of("depth")))
+            Some(or_exit(get_depth(command.value_of("depth"))))
         } else if command.is_present("depth") {
-            eprintln!(
-                "{}: flag --all is not compatible with --depth",
-                "Warning".yellow()
-            );
+            warnings.note("flag --all is not compatible with --depth");
             None
         } else {
             None
@@ -320,7 +836,7 @@ fn main() {
 
         // set number to print out
         let num_int = if command.is_present("count") {
-            Some(get_num(command.value_of("count")))
+            Some(or_exit(get_num(command.value_of("count"))))
         } else {
             None
         };
@@ -328,28 +844,94 @@ fn main() {
         // set whether to print files too
         let print_files = command.is_present("files");
 
+        // set output format
+        let format = or_exit(get_format(
+            command.value_of("format"),
+            command.is_present("null"),
+        ));
+        let opts = DisplayOptions {
+            format,
+            bars: command.is_present("bars"),
+            counts: command.is_present("count-entries"),
+            units,
+            time: get_time(command.is_present("time"), command.is_present("atime")),
+        };
+
+        // set age filters
+        let age = or_exit(get_age_filter(
+            command.value_of("older-than"),
+            command.value_of("newer-than"),
+            command.is_present("atime"),
+        ));
+
         // set path to dirs
         let dirs = get_dirs(command.values_of("dir"));
 
         for dir in dirs {
-            // set regex for exclusions
-            let regex = if let Some(n) = command.value_of("excludes") {
-                Some(n)
+            let scan_opts = ScanOptions::new(&dir)
+                .warnings(warnings.clone())
+                .max_depth(depth)
+                .excludes(command.value_of("excludes").map(check_regex).map(or_exit))
+                .blocks(blocks)
+                .count_links(count_links)
+                .follow_symlinks(follow)
+                .one_file_system(one_fs)
+                .cache(cache.clone());
+
+            // get relevant filenames &c. and sort them; if we only want the largest few, keep
+            // just those while reading.
+            let v_sorted = if let Some(n) = num_int {
+                let mut top = TopN::new(n, min_bytes, !print_files, depth, age);
+                scan_each(&scan_opts, &mut |e| top.push(e)).map(|totals| top.into_tree(totals))
             } else {
-                None
+                scan(&scan_opts.age(age)).map(|v| v.sort(num_int, min_bytes, !print_files, depth))
             };
-
-            // get relevant filenames &c.
-            let v = match regex {
-                Some(r) => read_all(&dir, 0, depth, Some(&check_regex(r)), &None, false, false),
-                _ => read_all(&dir, 0, depth, None, &None, false, false),
+            let mut v_sorted = match v_sorted {
+                Ok(v) => v,
+                Err(e) => {
+                    report(&e);
+                    continue;
+                }
             };
 
-            // sort them
-            let mut v_sorted = v.sort(num_int, min_bytes, !print_files, depth);
-
             // display sorted filenames
-            v_sorted.display_tree(&dir);
+            v_sorted.display(&dir, &opts);
+        }
+    }
+
+    if let Some(summary) = warnings.summary() {
+        warnings.note(format!("skipped {}", summary));
+        if strict(&matches) {
+            exit(0x0001);
         }
     }
+    if failed.get() > 0 && failed.get() >= roots(&matches) {
+        exit(0x0001);
+    }
+}
+
+/// Whether `--silent` was passed, either before or after the subcommand.
+fn silent(matches: &ArgMatches) -> bool {
+    matches.is_present("silent") || matches.subcommand().1.is_some_and(silent)
+}
+
+/// Whether `--strict` was passed to the subcommand that ran, e.g. `sn snapshot save --strict`.
+fn strict(matches: &ArgMatches) -> bool {
+    matches.is_present("strict") || matches.subcommand().1.is_some_and(strict)
+}
+
+/// How many directories the subcommand that ran was asked to read; one if it was given none.
+fn roots(matches: &ArgMatches) -> usize {
+    match matches.subcommand().1 {
+        Some(command) => roots(command),
+        None => matches.values_of("dir").map_or(1, |dirs| dirs.count()),
+    }
+}
+
+/// Print an error in the command-line arguments and exit.
+fn or_exit<T>(r: Result<T, Internal>) -> T {
+    r.unwrap_or_else(|e| {
+        eprintln!("{}: {}", "Error".red(), e);
+        exit(0x0f01)
+    })
 }
@@ -0,0 +1,163 @@
+//! Machine-readable output formats for the listing subcommands.
+
+use std::fmt::Write;
+use std::io;
+use types::{TimeKind, Units};
+
+/// How results should be written to stdout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored, human-readable sizes (the default).
+    #[default]
+    Human,
+    /// One JSON object per directory searched.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// Tab-separated values, suitable for `sort -n` and `cut`.
+    Tsv,
+    /// `bytes<TAB>path` records terminated by NUL, suitable for `xargs -0`.
+    Null,
+}
+
+/// Settings for how results are displayed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayOptions {
+    pub format: OutputFormat,
+    /// Draw a bar next to each entry showing its share of the total.
+    pub bars: bool,
+    pub units: Units,
+    /// Show how many files, directories and symlinks each entry holds.
+    pub counts: bool,
+    /// Show when each entry was last modified or accessed.
+    pub time: Option<TimeKind>,
+}
+
+/// Draw a bar `width` characters wide, filled in proportion to `fraction`, using Unicode block
+/// elements so that partially filled cells are shown too.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::output::bar;
+///
+/// assert_eq!(bar(0.5, 4), "██  ");
+/// assert_eq!(bar(1.0 / 16.0, 2), "▏ ");
+/// ```
+pub fn bar(fraction: f64, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+    let fraction = fraction.clamp(0.0, 1.0);
+    let eighths = (fraction * width as f64 * 8.0).round() as usize;
+    let mut out = String::with_capacity(width * 3);
+    for _ in 0..eighths / 8 {
+        out.push('█');
+    }
+    if eighths / 8 < width {
+        out.push(PARTIAL[eighths % 8]);
+        for _ in eighths / 8 + 1..width {
+            out.push(' ');
+        }
+    }
+    out
+}
+
+/// Format a time given in seconds since the Unix epoch as a `YYYY-MM-DD` date (UTC). Unknown
+/// times, given as zero, are shown as `-`.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::output::date;
+///
+/// assert_eq!(date(1_700_000_000), "2023-11-14");
+/// assert_eq!(date(0), "         -");
+/// ```
+pub fn date(secs: u64) -> String {
+    if secs == 0 {
+        return format!("{:>10}", "-");
+    }
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// Write raw bytes to standard output, e.g. for paths that are not valid unicode.
+pub fn print_bytes(b: &[u8]) {
+    use std::io::Write;
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(b).expect("failed printing to stdout");
+}
+
+/// Escape a string so that it can be embedded in a JSON document. The surrounding quotes are
+/// included.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::output::json_string;
+///
+/// assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+/// ```
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote a field for CSV output if it contains a separator, quote, or line break.
+///
+/// # Examples
+///
+/// ```
+/// use liboskar::output::csv_field;
+///
+/// assert_eq!(csv_field("plain"), "plain");
+/// assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+/// ```
+pub fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Escape tabs, line breaks and backslashes so that a field fits on one line of TSV output.
+pub fn tsv_field(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,350 @@
+//! A single entry point for reading a directory tree, configured with a `ScanOptions` builder
+//! rather than the long lists of arguments the walkers take.
+
+use cache::{save_cache, DirCache};
+use error::{Internal, Warnings};
+use regex::bytes::{Regex, RegexSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::Arc;
+use types::{AgeFilter, FileTree, Totals};
+use utils::{device, HardLinks, Symlinks};
+use walk_parallel::*;
+
+/// What to read, and how to count it. Every option starts off as `sn` does without flags,
+/// except that files are included along with directories.
+///
+/// ```
+/// use liboskar::scan::*;
+///
+/// let opts = ScanOptions::new("src").max_depth(1).threshold(1024);
+/// let tree = scan(&opts).unwrap();
+/// assert!(tree.children().iter().all(|e| e.bytes.get() > 1024));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    path: PathBuf,
+    max_depth: Option<u8>,
+    threshold: Option<u64>,
+    files: bool,
+    excludes: Option<Regex>,
+    ignores: Option<RegexSet>,
+    vimtags: bool,
+    artifacts_only: bool,
+    blocks: bool,
+    count_links: bool,
+    follow_symlinks: bool,
+    one_file_system: bool,
+    threads: usize,
+    cache: Option<PathBuf>,
+    age: AgeFilter,
+    warnings: Arc<Warnings>,
+}
+
+impl ScanOptions {
+    pub fn new<P: Into<PathBuf>>(path: P) -> ScanOptions {
+        ScanOptions {
+            path: path.into(),
+            max_depth: None,
+            threshold: None,
+            files: true,
+            excludes: None,
+            ignores: None,
+            vimtags: false,
+            artifacts_only: false,
+            blocks: false,
+            count_links: false,
+            follow_symlinks: false,
+            one_file_system: false,
+            threads: 1,
+            cache: None,
+            age: AgeFilter::default(),
+            warnings: Arc::new(Warnings::new()),
+        }
+    }
+
+    /// The directory (or file) to read.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How deep to list entries. Anything deeper is still counted towards its parent.
+    pub fn max_depth<D: Into<Option<u8>>>(mut self, max_depth: D) -> ScanOptions {
+        self.max_depth = max_depth.into();
+        self
+    }
+
+    /// Leave out entries of this many bytes or fewer.
+    pub fn threshold<T: Into<Option<u64>>>(mut self, threshold: T) -> ScanOptions {
+        self.threshold = threshold.into();
+        self
+    }
+
+    /// Whether to list files, or only directories.
+    pub fn files(mut self, files: bool) -> ScanOptions {
+        self.files = files;
+        self
+    }
+
+    /// Skip paths matching a regular expression.
+    pub fn excludes<R: Into<Option<Regex>>>(mut self, excludes: R) -> ScanOptions {
+        self.excludes = excludes.into();
+        self
+    }
+
+    /// Paths to treat as ignored by version control in artifact mode, on top of any
+    /// `.gitignore` files found along the way.
+    pub fn ignores<I: Into<Option<RegexSet>>>(mut self, ignores: I) -> ScanOptions {
+        self.ignores = ignores.into();
+        self
+    }
+
+    /// Whether `tags` files generated for vim or emacs count as artifacts.
+    pub fn vimtags(mut self, vimtags: bool) -> ScanOptions {
+        self.vimtags = vimtags;
+        self
+    }
+
+    /// Only count build artifacts.
+    pub fn artifacts_only(mut self, artifacts_only: bool) -> ScanOptions {
+        self.artifacts_only = artifacts_only;
+        self
+    }
+
+    /// Count disk usage (allocated blocks) rather than apparent sizes.
+    pub fn blocks(mut self, blocks: bool) -> ScanOptions {
+        self.blocks = blocks;
+        self
+    }
+
+    /// Count every hard link to a file, rather than each file once.
+    pub fn count_links(mut self, count_links: bool) -> ScanOptions {
+        self.count_links = count_links;
+        self
+    }
+
+    /// Follow symlinks to files and directories, reading each target once.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> ScanOptions {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Don't descend into directories on other filesystems.
+    pub fn one_file_system(mut self, one_file_system: bool) -> ScanOptions {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// How many threads to read with. With more than one, the entries of a directory come back
+    /// in no particular order.
+    pub fn threads(mut self, threads: usize) -> ScanOptions {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Reuse directory listings kept in this file since the last scan, and update it.
+    pub fn cache<C: Into<Option<PathBuf>>>(mut self, cache: C) -> ScanOptions {
+        self.cache = cache.into();
+        self
+    }
+
+    /// Only keep entries last modified (or accessed) within some window.
+    pub fn age(mut self, age: AgeFilter) -> ScanOptions {
+        self.age = age;
+        self
+    }
+
+    /// Count problems that don't stop a scan, such as unreadable directories, in `warnings`.
+    /// Sharing one counter between scans gives a summary for all of them.
+    pub fn warnings(mut self, warnings: Arc<Warnings>) -> ScanOptions {
+        self.warnings = warnings;
+        self
+    }
+
+    /// A `Walk` that reads in parallel with these options.
+    pub fn walk(&self) -> Walk {
+        let mut w = Walk::new(self.path.clone(), self.threads);
+        if let Some(d) = self.max_depth {
+            w.set_depth(d);
+        }
+        if let Some(b) = self.threshold {
+            w.set_threshold(b);
+        }
+        if self.files {
+            w.with_files();
+        }
+        if let Some(ref e) = self.excludes {
+            w.set_regex(e.clone());
+        }
+        if let Some(ref i) = self.ignores {
+            w.set_gitignore(i.clone());
+        }
+        if self.vimtags {
+            w.vimtags();
+        }
+        if self.artifacts_only {
+            w.artifacts_only();
+        }
+        if self.blocks {
+            w.blocks();
+        }
+        if self.count_links {
+            w.count_links();
+        }
+        if self.follow_symlinks {
+            w.follow_symlinks();
+        }
+        if self.one_file_system {
+            w.one_file_system();
+        }
+        w.set_age(self.age);
+        w.set_warnings(self.warnings.clone());
+        w
+    }
+
+    fn device(&self) -> Option<u64> {
+        if self.one_file_system {
+            device(&self.path)
+        } else {
+            None
+        }
+    }
+
+    fn links(&self) -> HardLinks {
+        HardLinks::new(self.count_links, self.follow_symlinks)
+    }
+
+    fn symlinks(&self) -> Symlinks {
+        Symlinks::new(self.follow_symlinks)
+    }
+
+    /// Whether the path is a directory, or an error if there is nothing there.
+    fn check(&self) -> Result<bool, Internal> {
+        match fs::metadata(&self.path) {
+            Ok(m) => Ok(m.is_dir()),
+            Err(_) => Err(Internal::PathDoesNotExist(self.path.clone())),
+        }
+    }
+}
+
+/// Read a directory tree. Entries are filtered by depth, size and age as the options say.
+pub fn scan(opts: &ScanOptions) -> Result<FileTree, Internal> {
+    let is_dir = opts.check()?;
+    let cache = opts
+        .cache
+        .as_ref()
+        .map(|p| Arc::new(DirCache::open(p, &opts.warnings)));
+
+    let tree = if opts.threads > 1 && is_dir {
+        let mut w = opts.walk();
+        if let Some(ref c) = cache {
+            w.set_cache(c.clone());
+        }
+        collect_parallel(w)?
+    } else {
+        let (links, symlinks) = (opts.links(), opts.symlinks());
+        if opts.excludes.is_some()
+            || opts.ignores.is_some()
+            || opts.artifacts_only
+            || cache.is_some()
+        {
+            read_all(
+                &opts.path,
+                0,
+                opts.max_depth,
+                opts.excludes.as_ref(),
+                &opts.ignores,
+                opts.vimtags,
+                opts.artifacts_only,
+                opts.blocks,
+                &links,
+                opts.device(),
+                &symlinks,
+                cache.as_deref(),
+                &opts.warnings,
+            )?
+        } else {
+            read_all_fast(
+                &opts.path,
+                0,
+                opts.max_depth,
+                opts.blocks,
+                &links,
+                opts.device(),
+                &symlinks,
+                &opts.warnings,
+            )?
+        }
+    };
+
+    save_cache(
+        cache.as_deref(),
+        slice::from_ref(&opts.path),
+        &opts.warnings,
+    );
+    Ok(tree
+        .filtered(opts.threshold, !opts.files, opts.max_depth)
+        .filter_age(&opts.age))
+}
+
+/// Read a directory tree, passing each file and directory to `visitor` as soon as its size is
+/// known, with the contents of a directory coming before the directory itself. Entries are
+/// passed on as they are, without filtering by size or age, but excludes, ignores and artifact
+/// detection apply as they do for `scan`. Returns the totals for the whole tree, not counting
+/// directories the visitor skipped.
+///
+/// ```
+/// use liboskar::scan::*;
+/// use liboskar::types::NamePair;
+/// use liboskar::walk_parallel::Visitor;
+/// use std::path::{Path, PathBuf};
+///
+/// // the files under `src`, without looking inside `src/testdata`
+/// struct Files(Vec<PathBuf>);
+///
+/// impl Visitor for Files {
+///     fn enter(&mut self, path: &Path, _depth: u8) -> bool {
+///         !path.ends_with("testdata")
+///     }
+///
+///     fn visit(&mut self, entry: NamePair) {
+///         if !entry.is_dir() {
+///             self.0.push(entry.name);
+///         }
+///     }
+/// }
+///
+/// let mut files = Files(Vec::new());
+/// scan_each(&ScanOptions::new("src"), &mut files).unwrap();
+/// assert!(files.0.contains(&PathBuf::from("src/lib.rs")));
+/// assert!(files.0.iter().all(|p| !p.starts_with("src/testdata")));
+/// ```
+pub fn scan_each<V>(opts: &ScanOptions, visitor: &mut V) -> Result<Totals, Internal>
+where
+    V: Visitor,
+{
+    opts.check()?;
+    let cache = opts
+        .cache
+        .as_ref()
+        .map(|p| DirCache::open(p, &opts.warnings));
+    let totals = stream_all(
+        &opts.path,
+        0,
+        opts.max_depth,
+        opts.excludes.as_ref(),
+        &opts.ignores,
+        opts.vimtags,
+        opts.artifacts_only,
+        opts.blocks,
+        &opts.links(),
+        opts.device(),
+        &opts.symlinks(),
+        cache.as_ref(),
+        &opts.warnings,
+        visitor,
+    )?;
+    save_cache(cache.as_ref(), slice::from_ref(&opts.path), &opts.warnings);
+    Ok(totals)
+}
@@ -0,0 +1,216 @@
+//! Save a scan to a file, and compare two scans to see which directories have grown or shrunk
+//! in between.
+
+use colored::*;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use types::{EntryCount, FileSize, FileTree, NamePair, Times, Totals, Units};
+use utils::{parse_field, path_bytes, path_from_bytes};
+
+/// The start of the first line of a snapshot, so that other files aren't mistaken for one. The
+/// rest of the line says which sizes were added up.
+const SNAPSHOT_HEADER: &str = "sn snapshot 1";
+
+/// A scan read back by `read_snapshot`.
+#[derive(Debug)]
+pub struct Snapshot {
+    /// The directory that was scanned.
+    pub dir: PathBuf,
+    pub tree: FileTree,
+    /// Whether sizes are the disk space allocated to files (`--disk-usage`) rather than their
+    /// apparent size. Scans taken with different sizes can't be compared.
+    pub blocks: bool,
+}
+
+/// A directory whose size differs between two scans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Path relative to the directory that was scanned.
+    pub path: PathBuf,
+    /// Size in the earlier scan, if the directory was there.
+    pub old: Option<FileSize>,
+    /// Size in the later scan, if the directory is still there.
+    pub new: Option<FileSize>,
+}
+
+impl Change {
+    /// Number of bytes the directory grew by; negative if it shrank.
+    pub fn delta(&self) -> i128 {
+        let get = |s: Option<FileSize>| i128::from(s.map_or(0, FileSize::get));
+        get(self.new) - get(self.old)
+    }
+}
+
+fn to_record(entry: &NamePair) -> Vec<u8> {
+    let mut out = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+        entry.bytes.get(),
+        entry.count.files,
+        entry.count.dirs,
+        entry.count.symlinks,
+        entry.times.modified,
+        entry.times.accessed,
+        entry.depth(),
+        entry.is_dir()
+    )
+    .into_bytes();
+    out.extend_from_slice(&path_bytes(&entry.name));
+    out.push(b'\0');
+    out
+}
+
+fn from_record(record: &[u8]) -> Option<NamePair> {
+    let mut fields = record.splitn(9, |c| *c == b'\t');
+    let size = FileSize::new(parse_field(fields.next())?);
+    let count = EntryCount {
+        files: parse_field(fields.next())?,
+        dirs: parse_field(fields.next())?,
+        symlinks: parse_field(fields.next())?,
+    };
+    let times = Times {
+        modified: parse_field(fields.next())?,
+        accessed: parse_field(fields.next())?,
+    };
+    let depth = parse_field(fields.next())?;
+    let is_dir = parse_field(fields.next())?;
+    let name = path_from_bytes(fields.next()?);
+    Some(
+        NamePair::new(name, size, depth, is_dir).with_totals(Totals {
+            size,
+            count,
+            times,
+            own: None,
+        }),
+    )
+}
+
+fn size_mode(blocks: bool) -> &'static str {
+    if blocks {
+        "disk-usage"
+    } else {
+        "apparent-size"
+    }
+}
+
+/// Write a scan of `init_dir` in the format read by `read_snapshot`; `blocks` says whether the
+/// scan counted allocated blocks rather than apparent sizes. The whole tree is written, entries
+/// with a size of zero included. After a header line naming the sizes used, each entry is a
+/// record of the form
+/// `bytes<TAB>files<TAB>dirs<TAB>symlinks<TAB>modified<TAB>accessed<TAB>depth<TAB>is_dir<TAB>path`
+/// ending in a NUL. The contents of a directory come before the directory itself, and the root
+/// comes last.
+pub fn write_snapshot<W: Write>(
+    tree: &FileTree,
+    init_dir: &Path,
+    blocks: bool,
+    out: &mut W,
+) -> io::Result<()> {
+    let root =
+        NamePair::new(init_dir.to_path_buf(), tree.file_size, 0, true).with_totals(tree.totals());
+    writeln!(out, "{} {}", SNAPSHOT_HEADER, size_mode(blocks))?;
+    for entry in tree.entries().into_iter().chain(Some(&root)) {
+        out.write_all(&to_record(entry))?;
+    }
+    Ok(())
+}
+
+fn parse_snapshot(b: &[u8]) -> Option<Snapshot> {
+    let rest = b
+        .strip_prefix(SNAPSHOT_HEADER.as_bytes())?
+        .strip_prefix(b" ")?;
+    let (blocks, rest) = [true, false].iter().find_map(|&blocks| {
+        let rest = rest
+            .strip_prefix(size_mode(blocks).as_bytes())?
+            .strip_prefix(b"\n")?;
+        Some((blocks, rest))
+    })?;
+    let mut tree = FileTree::new();
+    for record in rest.split(|c| *c == b'\0') {
+        let entry = from_record(record)?;
+        if entry.depth() == 0 {
+            tree.add_totals(Totals {
+                size: entry.bytes,
+                count: entry.count,
+                times: entry.times,
+                own: None,
+            });
+            return Some(Snapshot {
+                dir: entry.name,
+                tree,
+                blocks,
+            });
+        }
+        tree.push_post_order(entry);
+    }
+    None
+}
+
+/// Read a scan saved by `write_snapshot`, along with the directory that was scanned and the
+/// sizes it added up.
+pub fn read_snapshot<R: Read>(mut input: R) -> io::Result<Snapshot> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    parse_snapshot(&bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a valid snapshot"))
+}
+
+/// Compare the directories in two scans, largest change first. Directories are matched by
+/// their path relative to the directory each scan was taken of, so a scan can be compared with
+/// one of a copy or a different mount point.
+pub fn diff(old: &FileTree, old_dir: &Path, new: &FileTree, new_dir: &Path) -> Vec<Change> {
+    let old = old.dir_sizes(old_dir);
+    let new = new.dir_sizes(new_dir);
+    let paths: HashSet<&PathBuf> = old.keys().chain(new.keys()).collect();
+    let mut changes: Vec<Change> = paths
+        .into_iter()
+        .map(|path| Change {
+            path: path.to_path_buf(),
+            old: old.get(path).cloned(),
+            new: new.get(path).cloned(),
+        })
+        .filter(|c| c.delta() != 0)
+        .collect();
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    changes
+}
+
+/// Print changes one per line, with directories that grew in red and those that shrank in
+/// green.
+pub fn display_changes(changes: &[Change], init_dir: &Path, units: Units) {
+    for change in changes {
+        let delta = change.delta();
+        let size = FileSize::new(delta.unsigned_abs() as u64).format(units);
+        let path = if change.path.as_os_str().is_empty() {
+            init_dir.to_path_buf()
+        } else {
+            init_dir.join(&change.path)
+        };
+        let note = match (change.old, change.new) {
+            (None, _) => " (new)",
+            (_, None) => " (removed)",
+            _ => "",
+        };
+        if delta > 0 {
+            println!(
+                "{}\t {}{}",
+                format!("+{}", size).red(),
+                path.display(),
+                note
+            );
+        } else {
+            println!(
+                "{}\t {}{}",
+                format!("-{}", size).green(),
+                path.display(),
+                note
+            );
+        }
+    }
+}
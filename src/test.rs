@@ -1,6 +1,9 @@
+use export::*;
 use gitignore::*;
 use prelude::*;
-use std::path::PathBuf;
+use snapshot::*;
+use std::path::{Path, PathBuf};
+use types::*;
 
 #[test]
 fn cabal_regex_ignore() {
@@ -20,5 +23,636 @@ fn bug_regex_ignore() {
 #[test]
 fn test_parser() {
     let cli_input = "30M";
-    assert_eq!(Some(30 * 1024 * 1024), threshold(Some(cli_input)));
+    assert_eq!(
+        Some(30 * 1024 * 1024),
+        threshold(Some(cli_input), Units::Iec).unwrap()
+    );
+    assert_eq!(
+        Some(30 * 1000 * 1000),
+        threshold(Some(cli_input), Units::Si).unwrap()
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let mut tree = FileTree::new();
+    tree.push(PathBuf::from("a/\"b\""), FileSize::new(3), None, 1, false);
+    assert_eq!(
+        tree.to_json(Path::new("a"), &DisplayOptions::default()),
+        r#"{"root":"a","total":3,"entries":[{"path":"a/\"b\"","bytes":3,"depth":1,"is_dir":false}]}"#
+    );
+}
+
+#[test]
+fn test_delimited_output() {
+    let mut tree = FileTree::new();
+    tree.push(PathBuf::from("a/b c"), FileSize::new(3), None, 1, false);
+    assert_eq!(
+        tree.to_records(
+            Path::new("a"),
+            &DisplayOptions {
+                format: OutputFormat::Tsv,
+                ..DisplayOptions::default()
+            }
+        ),
+        b"3\t1\tfalse\ta/b c\n3\t0\ttrue\ta\n"
+    );
+    assert_eq!(
+        tree.to_records(
+            Path::new("a"),
+            &DisplayOptions {
+                format: OutputFormat::Null,
+                ..DisplayOptions::default()
+            }
+        ),
+        b"3\ta/b c\0"
+    );
+}
+
+#[test]
+fn test_ncdu_tree() {
+    let dir = Path::new("src/testdata");
+    let tree = scan(&ScanOptions::new(dir).count_links(true)).unwrap();
+    let mut out = Vec::new();
+    write_ncdu(&tree, dir, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    // every entry has both sizes and its inode, whichever sizes the scan added up
+    let entries = tree.entries().len() + 1;
+    for key in &["{\"name\":", ",\"asize\":", ",\"dsize\":", ",\"ino\":"] {
+        assert_eq!(out.matches(key).count(), entries);
+    }
+    assert!(out.contains("[{\"name\":\"subdir\",\"asize\":"));
+    assert!(out.contains("{\"name\":\"junkfile1\",\"asize\":8,\"dsize\":"));
+    let sizes = |key: &str| -> u64 {
+        out.split(key)
+            .skip(1)
+            .map(|s| {
+                s[..s.find(|c: char| !c.is_ascii_digit()).unwrap()]
+                    .parse::<u64>()
+                    .unwrap()
+            })
+            .sum()
+    };
+    let files: u64 = tree
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir())
+        .map(|e| e.bytes.get())
+        .sum();
+    assert_eq!(files, tree.file_size.get());
+    assert!(sizes("\"asize\":") >= files);
+    let blocks = scan(&ScanOptions::new(dir).count_links(true).blocks(true)).unwrap();
+    assert_eq!(sizes("\"dsize\":"), blocks.file_size.get());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ncdu_hard_links() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("sn-ncdu-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a"), [0u8; 10]).unwrap();
+    fs::hard_link(dir.join("a"), dir.join("sub/b")).unwrap();
+    fs::write(dir.join("c"), [0u8; 5]).unwrap();
+
+    let tree = scan(&ScanOptions::new(&dir).count_links(true)).unwrap();
+    let mut out = Vec::new();
+    write_ncdu(&tree, &dir, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let ino = tree.get(dir.join("a")).unwrap().info.unwrap().ino;
+    fs::remove_dir_all(&dir).unwrap();
+
+    // both links are written, for ncdu to count once
+    let link = format!(",\"ino\":{},\"hlnkc\":true}}", ino);
+    assert_eq!(out.matches(&link).count(), 2);
+    assert_eq!(out.matches("hlnkc").count(), 2);
+}
+
+#[test]
+fn test_snapshot() {
+    let dir = PathBuf::from("src/testdata");
+    let tree = scan(&ScanOptions::new(&dir)).unwrap();
+    let mut saved = Vec::new();
+    write_snapshot(&tree, &dir, false, &mut saved).unwrap();
+    let read = read_snapshot(&saved[..]).unwrap();
+    assert_eq!(read.dir, dir);
+    assert!(!read.blocks);
+    assert_eq!(read.tree.file_size, tree.file_size);
+    assert_eq!(read.tree.entries().len(), tree.entries().len());
+    assert!(diff(&tree, &dir, &read.tree, &read.dir).is_empty());
+
+    // the header records which sizes were added up
+    let blocks = scan(&ScanOptions::new(&dir).blocks(true)).unwrap();
+    let mut saved = Vec::new();
+    write_snapshot(&blocks, &dir, true, &mut saved).unwrap();
+    assert!(saved.starts_with(b"sn snapshot 1 disk-usage\n"));
+    let read = read_snapshot(&saved[..]).unwrap();
+    assert!(read.blocks);
+    assert_eq!(read.tree.file_size, blocks.file_size);
+    assert!(read_snapshot(&b"sn snapshot 1\n"[..]).is_err());
+
+    // a directory left out of the later scan shows up as removed
+    let subdir = tree.get("src/testdata/subdir").unwrap().bytes;
+    let excludes = check_regex("/subdir$").unwrap();
+    let later = scan(&ScanOptions::new(&dir).excludes(excludes)).unwrap();
+    let changes = diff(&tree, &dir, &later, &dir);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].path, PathBuf::new());
+    assert_eq!(
+        changes[1],
+        Change {
+            path: PathBuf::from("subdir"),
+            old: Some(subdir),
+            new: None,
+        }
+    );
+    assert_eq!(changes[0].delta(), changes[1].delta());
+}
+
+#[test]
+fn test_scan_options() {
+    use std::fs;
+
+    let names = |tree: &FileTree| {
+        let mut names: Vec<_> = tree.entries().iter().map(|e| e.name.clone()).collect();
+        names.sort();
+        names
+    };
+    let opts = ScanOptions::new("src/testdata").max_depth(2).threshold(100);
+    let single = scan(&opts).unwrap();
+    let parallel = scan(&opts.clone().threads(4)).unwrap();
+    assert_eq!(single.file_size, parallel.file_size);
+    assert_eq!(single.count, parallel.count);
+    assert_eq!(names(&single), names(&parallel));
+    assert!(single.entries().iter().all(|e| e.bytes.get() > 100));
+
+    let dir = std::env::temp_dir().join(format!("sn-artifacts-{}", std::process::id()));
+    for (file, len) in &[
+        ("a.o", 10),
+        ("src/b.rs", 20),
+        ("sub/c.hi", 30),
+        ("sub/deep/d.o", 40),
+        ("sub/deep/e.rs", 50),
+        ("proj/Cargo.toml", 60),
+        ("proj/target/debug/f", 70),
+    ] {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; *len]).unwrap();
+    }
+    let artifacts = ScanOptions::new(&dir).artifacts_only(true);
+    let single = scan(&artifacts).unwrap();
+    let parallel = scan(&artifacts.clone().threads(4)).unwrap();
+    let files = |tree: &FileTree| tree.entries().iter().filter(|e| !e.is_dir()).count();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(single.file_size, parallel.file_size);
+    assert_eq!(single.count, parallel.count);
+    assert_eq!(names(&single), names(&parallel));
+    assert_eq!(files(&single), 3);
+    assert_eq!(single.get(dir.join("proj/target")).unwrap().bytes.get(), 70);
+
+    let missing = ScanOptions::new("src/testdata/missing");
+    assert!(scan(&missing).is_err());
+}
+
+#[test]
+fn test_unreadable_ignore() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("sn-ignore-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(".gitignore"), b"caf\xe9.o\n").unwrap();
+    fs::write(dir.join("a.o"), [0u8; 10]).unwrap();
+    let tree = scan(&ScanOptions::new(&dir).artifacts_only(true));
+    let ignores = mk_ignores(&dir, &None, &Warnings::quiet());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(tree.unwrap().file_size.get(), 10);
+    assert_eq!(ignores.map(|i| i.len()), Some(0));
+}
+
+#[test]
+fn test_errors() {
+    let missing = Path::new("src/testdata/missing");
+    match scan(&ScanOptions::new(missing)).err() {
+        Some(Internal::PathDoesNotExist(p)) => assert_eq!(p, missing),
+        other => panic!("expected a missing path, got {:?}", other),
+    }
+    let file = Path::new("src/test.rs");
+    match read_size(
+        file,
+        None,
+        &None,
+        false,
+        false,
+        false,
+        &HardLinks::new(false, false),
+        None,
+        &Symlinks::new(false),
+        None,
+        &Warnings::new(),
+    ) {
+        Err(Internal::NotDirectory(p)) => assert_eq!(p, file),
+        other => panic!("expected a file, got {:?}", other),
+    }
+
+    assert!(check_regex("(").is_err());
+    assert!(get_depth(Some("-1")).is_err());
+    assert!(threshold(Some("12X"), Units::Iec).is_err());
+    assert!(get_format(Some("xml"), false).is_err());
+}
+
+#[test]
+fn test_visitor() {
+    struct Pruned {
+        entered: Vec<(PathBuf, u8)>,
+        seen: Vec<PathBuf>,
+    }
+    impl Visitor for Pruned {
+        fn enter(&mut self, path: &Path, depth: u8) -> bool {
+            self.entered.push((path.to_path_buf(), depth));
+            !path.ends_with("subdir2")
+        }
+        fn visit(&mut self, entry: NamePair) {
+            self.seen.push(entry.name);
+        }
+    }
+
+    let opts = ScanOptions::new("src/testdata");
+    let full = scan(&opts).unwrap();
+    let mut pruned = Pruned {
+        entered: Vec::new(),
+        seen: Vec::new(),
+    };
+    let totals = scan_each(&opts, &mut pruned).unwrap();
+
+    let subdir2 = Path::new("src/testdata/subdir2");
+    let skipped = full.get(subdir2).unwrap().bytes.get();
+    assert_eq!(totals.size.get(), full.file_size.get() - skipped);
+    assert!(pruned.entered.contains(&(subdir2.to_path_buf(), 1)));
+    assert!(pruned.seen.iter().all(|p| !p.starts_with(subdir2)));
+    assert!(pruned
+        .seen
+        .contains(&PathBuf::from("src/testdata/subdir/file")));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json;
+    use std::fmt::Debug;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+    }
+
+    let opts = ScanOptions::new("src/testdata");
+    let tree = scan(&opts).unwrap();
+    round_trip(&tree);
+    round_trip(&tree.file_size);
+    round_trip(&tree.children()[0]);
+    round_trip(&scan_each(&opts, &mut |_: NamePair| {}).unwrap());
+
+    // names are written as strings, so formats like JSON can't hold names that aren't UTF-8
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = PathBuf::from(OsStr::from_bytes(b"caf\xe9.o"));
+        let entry = NamePair::new(name, FileSize::new(7), 1, false);
+        assert!(serde_json::to_string(&entry).is_err());
+    }
+}
+
+#[test]
+fn test_tree_structure() {
+    let tree = scan(&ScanOptions::new("src/testdata")).unwrap();
+    let subdir = tree.get("src/testdata/subdir2").unwrap();
+    assert_eq!(subdir.children().len(), 11);
+    let sum: u64 = subdir.children().iter().map(|c| c.bytes.get()).sum();
+    assert_eq!(sum, subdir.bytes.get());
+}
+
+#[test]
+fn test_indented_output() {
+    let opts = ScanOptions::new("src/testdata").max_depth(2).files(false);
+    let tree = scan(&opts).unwrap();
+    let lines = tree
+        .to_indented(Path::new("src/testdata"))
+        .into_iter()
+        .map(|(_, l)| l)
+        .collect::<Vec<String>>();
+    assert_eq!(
+        lines,
+        vec![
+            "src/testdata",
+            "├── gitignore-tests (54%)",
+            "├── subdir2 (25%)",
+            "│   └── nested (50%)",
+            "└── subdir (13%)",
+        ]
+    );
+}
+
+#[test]
+fn test_threshold_grammar() {
+    assert_eq!(parse_threshold("0.5k", Units::Iec).ok(), Some(512));
+    assert_eq!(parse_threshold("1P", Units::Si).ok(), Some(1000u64.pow(5)));
+    assert_eq!(parse_threshold("3kib", Units::Si).ok(), Some(3072));
+    assert!(parse_threshold("1.", Units::Iec).is_err());
+    assert!(parse_threshold("M", Units::Iec).is_err());
+    assert!(parse_threshold("20000000P", Units::Iec).is_err());
+}
+
+#[test]
+fn test_stream_order() {
+    let mut seen: Vec<(PathBuf, bool)> = Vec::new();
+    let opts = ScanOptions::new("src/testdata");
+    let totals = scan_each(&opts, &mut |e: NamePair| {
+        seen.push((e.name.clone(), e.children().is_empty()))
+    })
+    .unwrap();
+    assert_eq!(totals.size.get(), 1500);
+    let nested = seen
+        .iter()
+        .position(|e| e.0 == Path::new("src/testdata/subdir2/nested"));
+    let subdir2 = seen
+        .iter()
+        .position(|e| e.0 == Path::new("src/testdata/subdir2"));
+    assert!(nested < subdir2);
+    assert!(seen.iter().all(|e| e.1));
+}
+
+#[test]
+fn test_top_n() {
+    let opts = ScanOptions::new("src/testdata");
+    let mut top = TopN::new(4, Some(10), false, None, AgeFilter::default());
+    let totals = scan_each(&opts, &mut |e| top.push(e)).unwrap();
+    let top = top.into_tree(totals);
+    let sorted = scan(&opts).unwrap().sort(Some(4), Some(10), false, None);
+    let names = |t: &FileTree| {
+        t.entries()
+            .iter()
+            .map(|e| e.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&top), names(&sorted));
+    assert_eq!(top.file_size, sorted.file_size);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_unicode_names() {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::env::temp_dir().join(format!("sn-latin1-{}", std::process::id()));
+    let name = OsStr::from_bytes(b"caf\xe9.o");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(name), [0u8; 7]).unwrap();
+
+    let opts = ScanOptions::new(&dir);
+    let tree = scan(&opts).unwrap();
+    let excludes = check_regex(r"\.o$").unwrap();
+    let excluded = scan(&opts.clone().excludes(excludes)).unwrap();
+    let artifacts = scan(&opts.artifacts_only(true)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(tree.file_size.get(), 7);
+    assert_eq!(tree.children()[0].name, dir.join(name));
+    assert_eq!(excluded.file_size.get(), 0);
+    assert_eq!(artifacts.file_size.get(), 7);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hard_links() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("sn-links-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a"), [0u8; 10]).unwrap();
+    fs::hard_link(dir.join("a"), dir.join("sub/b")).unwrap();
+
+    let once = scan(&ScanOptions::new(&dir)).unwrap();
+    let every = scan(&ScanOptions::new(&dir).count_links(true)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(once.file_size.get(), 10);
+    assert_eq!(once.count.files, 1);
+    assert_eq!(every.file_size.get(), 20);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_cycles() {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("sn-symlinks-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/a"), [0u8; 10]).unwrap();
+    symlink("..", dir.join("sub/up")).unwrap();
+    symlink("sub/a", dir.join("b")).unwrap();
+
+    let followed = scan(&ScanOptions::new(&dir).follow_symlinks(true)).unwrap();
+    let not_followed = scan(&ScanOptions::new(&dir)).unwrap();
+    let mut broken = Vec::new();
+    fs::remove_file(dir.join("sub/a")).unwrap();
+    // still counted when not printed
+    let warnings = Arc::new(Warnings::quiet());
+    let opts = ScanOptions::new(&dir)
+        .follow_symlinks(true)
+        .warnings(warnings.clone());
+    scan(&opts).unwrap();
+    broken_symlinks(&dir, None, None, &warnings, &mut |p, t| {
+        broken.push((p.to_path_buf(), t.to_path_buf()))
+    })
+    .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(broken_symlinks(&dir, None, None, &warnings, &mut |_, _| {}).is_err());
+
+    assert_eq!(followed.file_size.get(), 10);
+    assert_eq!(not_followed.file_size.get(), 10);
+    assert_eq!(not_followed.count.symlinks, 2);
+    assert_eq!(broken, vec![(dir.join("b"), PathBuf::from("sub/a"))]);
+    assert_eq!(warnings.summary(), Some("1 broken symlink".to_string()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_special_files() {
+    use std::fs;
+    use std::process::Command;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("sn-fifo-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a"), [0u8; 10]).unwrap();
+    let made = Command::new("mkfifo")
+        .arg(dir.join("fifo"))
+        .status()
+        .is_ok_and(|s| s.success());
+
+    let warnings = Arc::new(Warnings::quiet());
+    for threads in &[1, 4] {
+        let opts = ScanOptions::new(&dir)
+            .warnings(warnings.clone())
+            .threads(*threads);
+        assert_eq!(scan(&opts).unwrap().file_size.get(), 10);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(made);
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_entry_counts() {
+    let tree = scan(&ScanOptions::new("src/testdata")).unwrap();
+    let subdir2 = tree.get("src/testdata/subdir2").unwrap();
+    let expected = EntryCount {
+        files: 20,
+        dirs: 2,
+        symlinks: 0,
+    };
+    assert_eq!(subdir2.count, expected);
+    let total = tree.children().iter().map(|e| e.count.total()).sum::<u64>();
+    assert_eq!(tree.count.total(), total + 1);
+}
+
+#[test]
+fn test_age_filter() {
+    assert_eq!(parse_duration("2w").ok(), Some(14 * 86400));
+    assert_eq!(parse_duration("36H").ok(), Some(36 * 3600));
+    assert_eq!(parse_duration("7").ok(), Some(7 * 86400));
+    assert!(parse_duration("d").is_err());
+
+    let at = |secs| Totals {
+        times: Times {
+            modified: secs,
+            accessed: secs,
+        },
+        ..Totals::default()
+    };
+    let names = |age: AgeFilter| {
+        let mut tree = FileTree::new();
+        for &(name, secs, depth, is_dir) in &[
+            ("d/old", 10, 2, false),
+            ("d/new", 100, 2, false),
+            ("d", 100, 1, true),
+        ] {
+            let entry = NamePair::new(PathBuf::from(name), FileSize::new(1), depth, is_dir);
+            tree.push_post_order(entry.with_totals(at(secs)));
+        }
+        tree.filter_age(&age)
+            .entries()
+            .iter()
+            .map(|e| e.name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // the directory is as new as its newest file, so old files outlive it
+    let older = AgeFilter {
+        older_than: Some(50),
+        ..AgeFilter::default()
+    };
+    assert_eq!(names(older), vec![PathBuf::from("d/old")]);
+    let newer = AgeFilter {
+        newer_than: Some(50),
+        ..AgeFilter::default()
+    };
+    assert_eq!(
+        names(newer),
+        vec![PathBuf::from("d/new"), PathBuf::from("d")]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dir_cache() {
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let base = std::env::temp_dir().join(format!("sn-cache-{}", std::process::id()));
+    let dir = base.join("tree");
+    let cache_file = base.join("cache");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a"), [0u8; 10]).unwrap();
+    fs::write(dir.join("sub/b"), [0u8; 20]).unwrap();
+
+    // listings are only reused once the directories have been left alone for a while
+    std::thread::sleep(std::time::Duration::from_millis(2100));
+
+    let opts = ScanOptions::new(&dir).cache(cache_file);
+    let read = || scan_each(&opts, &mut |_: NamePair| {}).unwrap();
+    let first = read();
+    assert_eq!(first.size.get(), 30);
+    let again = read();
+    assert_eq!((again.size, again.count), (first.size, first.count));
+
+    // a file grown in place leaves its directory alone, so the cached size is kept...
+    let mut b = OpenOptions::new()
+        .append(true)
+        .open(dir.join("sub/b"))
+        .unwrap();
+    b.write_all(&[0u8; 5]).unwrap();
+    assert_eq!(read().size.get(), 30);
+
+    // ...until something is added to or removed from the directory
+    fs::write(dir.join("sub/c"), [0u8; 1]).unwrap();
+    let last = read();
+    fs::remove_dir_all(&base).unwrap();
+
+    assert_eq!(last.size.get(), 36);
+    assert_eq!(last.count.files, 3);
+}
+
+#[test]
+fn test_corrupt_cache() {
+    use std::fs;
+    use std::sync::Arc;
+
+    let base = std::env::temp_dir().join(format!("sn-corrupt-{}", std::process::id()));
+    let dir = base.join("tree");
+    let cache_file = base.join("cache");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a"), [0u8; 10]).unwrap();
+    fs::write(dir.join("sub/b"), [0u8; 20]).unwrap();
+
+    let live = scan(&ScanOptions::new(&dir)).unwrap();
+    let opts = ScanOptions::new(&dir).cache(cache_file.clone());
+    scan(&opts).unwrap();
+    let saved = fs::read(&cache_file).unwrap();
+
+    // a cache cut off part way through, one with a record that doesn't parse, and something
+    // else entirely are all read as empty, with a warning
+    let corrupt: [&[u8]; 3] = [
+        &saved[..saved.len() - 1],
+        b"sn cache 2\nnot\ta\tcache\0",
+        b"\x89PNG",
+    ];
+    let mut results = Vec::new();
+    for bytes in &corrupt {
+        fs::write(&cache_file, bytes).unwrap();
+        let warnings = Arc::new(Warnings::quiet());
+        let tree = scan(&opts.clone().warnings(warnings.clone())).unwrap();
+        results.push((tree.file_size, tree.count, warnings.summary()));
+    }
+    fs::remove_dir_all(&base).unwrap();
+
+    for (size, count, summary) in results {
+        assert_eq!((size, count), (live.file_size, live.count));
+        assert_eq!(summary, Some("1 unreadable entry".to_string()));
+    }
 }
@@ -1,23 +1,99 @@
 extern crate pad;
 
 use self::pad::PadStr;
+use cache::FileInfo;
 use colored::*;
-use std::cmp::Ordering;
+use output::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt;
-use std::path::PathBuf;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utils::{path_bytes, size};
 
 /// This is just a wrapper around a `u64` so that we can implement our own `Display` trait for our
 /// file sizes.
-#[derive(Ord, Eq, PartialOrd, PartialEq, Copy, Clone)]
+#[derive(Ord, Eq, PartialOrd, PartialEq, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct FileSize {
     size: u64,
 }
 
+/// Which units to print file sizes (and read thresholds) in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    /// Powers of 1024: KiB, MiB, GiB, ...
+    #[default]
+    Iec,
+    /// Powers of 1000: kB, MB, GB, ...
+    Si,
+    /// Raw byte counts.
+    Bytes,
+}
+
+impl Units {
+    /// The number of bytes in a kilobyte (or kibibyte).
+    pub fn base(self) -> u64 {
+        match self {
+            Units::Si => 1000,
+            _ => 1024,
+        }
+    }
+
+    fn labels(self) -> [&'static str; 6] {
+        match self {
+            Units::Si => ["B", "kB", "MB", "GB", "TB", "PB"],
+            _ => ["B", "KiB", "MiB", "GiB", "TiB", "PiB"],
+        }
+    }
+}
+
 impl FileSize {
     pub fn new(i: u64) -> FileSize {
         FileSize { size: i }
     }
 
+    /// Format the size for display in the given units.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use liboskar::types::*;
+    ///
+    /// let size = FileSize::new(1536000);
+    /// assert_eq!(size.format(Units::Iec), "1.5  MiB");
+    /// assert_eq!(size.format(Units::Si), "1.5  MB");
+    /// assert_eq!(size.format(Units::Bytes), "1536000");
+    /// ```
+    pub fn format(self, units: Units) -> String {
+        if units == Units::Bytes {
+            return format!("{}", self.size);
+        }
+
+        let base = units.base();
+        let labels = units.labels();
+        let mut unit = 1;
+        let mut exponent = 0;
+        while exponent + 1 < labels.len() && self.size / unit >= base {
+            unit *= base;
+            exponent += 1;
+        }
+
+        let pre_size = if exponent == 0 || self.size / unit > 9 {
+            format!("{}", self.size / unit)
+        } else if self.size as f64 / unit as f64 >= 9.95 {
+            format!("{:.0}", self.size as f64 / unit as f64)
+        } else {
+            format!("{:.1}", self.size as f64 / unit as f64)
+        };
+        format!("{} {}", pre_size.pad_to_width(4), labels[exponent])
+    }
+
     pub fn add(&mut self, other: FileSize) {
         self.size += other.size;
     }
@@ -27,38 +103,471 @@ impl FileSize {
     }
 }
 
-#[derive(Debug)]
+/// How many files, directories and symlinks there are in a subtree. Like `du --inodes`, an entry
+/// counts itself, so a file has a count of one file, and an empty directory one directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryCount {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+}
+
+impl EntryCount {
+    pub fn add(&mut self, other: EntryCount) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.symlinks += other.symlinks;
+    }
+
+    /// The total number of entries, i.e. the number of inodes used (hard links aside).
+    pub fn total(self) -> u64 {
+        self.files + self.dirs + self.symlinks
+    }
+}
+
+/// Which timestamp to look at when filtering or displaying entries by age.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeKind {
+    #[default]
+    Modified,
+    Accessed,
+}
+
+/// When something was last modified and accessed, in seconds since the Unix epoch, or zero if
+/// that is not known. For a directory, these are the newest times of anything in it, the
+/// directory included, so that a directory is only as old as the last thing touched in it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Times {
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+fn epoch_secs(t: ::std::io::Result<SystemTime>) -> u64 {
+    t.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Times {
+    pub fn from_metadata(m: &Metadata) -> Times {
+        Times {
+            modified: epoch_secs(m.modified()),
+            accessed: epoch_secs(m.accessed()),
+        }
+    }
+
+    /// Keep whichever times are newer.
+    pub fn add(&mut self, other: Times) {
+        self.modified = self.modified.max(other.modified);
+        self.accessed = self.accessed.max(other.accessed);
+    }
+
+    pub fn get(self, kind: TimeKind) -> u64 {
+        match kind {
+            TimeKind::Modified => self.modified,
+            TimeKind::Accessed => self.accessed,
+        }
+    }
+}
+
+/// Everything gathered about a subtree while reading it: its size, how many entries it has, and
+/// when it was last touched.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Totals {
+    pub size: FileSize,
+    pub count: EntryCount,
+    pub times: Times,
+    /// The metadata of the file or directory at the top of the subtree, if it could be read.
+    /// Adding totals together leaves this alone.
+    pub own: Option<FileInfo>,
+}
+
+impl Totals {
+    /// The totals for a single file.
+    pub fn file(size: FileSize, m: &Metadata) -> Totals {
+        Totals {
+            size,
+            count: EntryCount {
+                files: 1,
+                ..EntryCount::default()
+            },
+            times: Times::from_metadata(m),
+            own: Some(FileInfo::from_metadata(m)),
+        }
+    }
+
+    /// The totals for a directory itself, apart from its contents. A directory's own size is only
+    /// counted towards disk usage (`blocks`), as `du` does; its apparent size depends on the
+    /// filesystem and says little about what is in it.
+    pub fn dir_itself(m: &Metadata, blocks: bool) -> Totals {
+        Totals {
+            size: FileSize::new(if blocks { size(m, true) } else { 0 }),
+            count: EntryCount::default(),
+            times: Times::from_metadata(m),
+            own: None,
+        }
+    }
+
+    pub fn add(&mut self, other: Totals) {
+        self.size.add(other.size);
+        self.count.add(other.count);
+        self.times.add(other.times);
+    }
+}
+
+/// Only keep entries that were last modified (or accessed) before or after a point in time,
+/// given in seconds since the Unix epoch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AgeFilter {
+    pub older_than: Option<u64>,
+    pub newer_than: Option<u64>,
+    pub kind: TimeKind,
+}
+
+impl AgeFilter {
+    pub fn is_empty(&self) -> bool {
+        self.older_than.is_none() && self.newer_than.is_none()
+    }
+
+    pub fn matches(&self, times: Times) -> bool {
+        let t = times.get(self.kind);
+        self.older_than.is_none_or(|c| t < c) && self.newer_than.is_none_or(|c| t >= c)
+    }
+}
+
+/// A single file or directory. Directories carry their own contents, so that a `FileTree` keeps
+/// the full hierarchy of what was read; `bytes` is always the total size of the subtree.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NamePair {
     pub bytes: FileSize,
     depth: u8,
-    pub name: String,
+    pub name: PathBuf,
     is_dir: bool,
+    pub count: EntryCount,
+    pub times: Times,
+    /// The entry's own metadata, apart from anything in it, if it was read.
+    pub info: Option<FileInfo>,
+    children: Vec<NamePair>,
 }
 
 fn sort_by_size(fst: &NamePair, snd: &NamePair) -> Ordering {
     fst.bytes.cmp(&snd.bytes)
 }
 
+fn keep(a: &NamePair, min_bytes: Option<u64>, dirs_only: bool, max_depth: Option<u8>) -> bool {
+    (if dirs_only { a.is_dir } else { true })
+        && Some(a.bytes) > min_bytes.map(FileSize::new)
+        && (max_depth.is_none() || Some(a.depth) <= max_depth)
+}
+
+// Since a directory is always at least as large as anything in it, and deeper than its parent,
+// anything we drop takes its children along with it.
+fn prune(
+    entries: Vec<NamePair>,
+    min_bytes: Option<u64>,
+    dirs_only: bool,
+    max_depth: Option<u8>,
+) -> Vec<NamePair> {
+    entries
+        .into_iter()
+        .filter(|a| keep(a, min_bytes, dirs_only, max_depth))
+        .map(|mut a| {
+            a.children = prune(a.children, min_bytes, dirs_only, max_depth);
+            a
+        })
+        .collect()
+}
+
+// Unlike the other filters, the age filter can drop a directory while keeping some of what is in
+// it, e.g. old files in a directory that has had something new added. Those move up a level.
+fn prune_age(entries: Vec<NamePair>, age: &AgeFilter) -> Vec<NamePair> {
+    let mut out = Vec::new();
+    for mut a in entries {
+        a.children = prune_age(a.children, age);
+        if age.matches(a.times) {
+            out.push(a);
+        } else {
+            out.append(&mut a.children);
+        }
+    }
+    out
+}
+
+// Flatten a list of entries so that each directory comes right after its contents, which is the
+// order in which they were read.
+fn flatten_into(entries: Vec<NamePair>, out: &mut Vec<NamePair>) {
+    for mut a in entries {
+        let children = ::std::mem::take(&mut a.children);
+        flatten_into(children, out);
+        out.push(a);
+    }
+}
+
+// Lay out a directory's contents as an indented tree, largest first, with each entry's share of
+// its parent.
+fn indent_into(
+    entries: &[NamePair],
+    parent: FileSize,
+    prefix: &str,
+    out: &mut Vec<(FileSize, String)>,
+) {
+    let mut shown = entries
+        .iter()
+        .filter(|a| a.bytes != FileSize::new(0))
+        .collect::<Vec<&NamePair>>();
+    shown.sort_by(|a, b| sort_by_size(b, a));
+
+    for (i, a) in shown.iter().enumerate() {
+        let last = i + 1 == shown.len();
+        let name = a
+            .name
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| a.name.display().to_string());
+        let percent = if parent.get() == 0 {
+            0.0
+        } else {
+            100.0 * a.bytes.get() as f64 / parent.get() as f64
+        };
+        out.push((
+            a.bytes,
+            format!(
+                "{}{}{} ({:.0}%)",
+                prefix,
+                if last { "└── " } else { "├── " },
+                name,
+                percent
+            ),
+        ));
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        indent_into(&a.children, a.bytes, &child_prefix, out);
+    }
+}
+
+fn walk_post_order<'a>(entries: &'a [NamePair], out: &mut Vec<&'a NamePair>) {
+    for a in entries {
+        walk_post_order(&a.children, out);
+        out.push(a);
+    }
+}
+
 impl NamePair {
-    pub fn new(path: String, bytes_in: FileSize, d: u8, b: bool) -> NamePair {
+    pub fn new(path: PathBuf, bytes_in: FileSize, d: u8, b: bool) -> NamePair {
+        let count = if b {
+            EntryCount {
+                dirs: 1,
+                ..EntryCount::default()
+            }
+        } else {
+            EntryCount {
+                files: 1,
+                ..EntryCount::default()
+            }
+        };
         NamePair {
             name: path,
             bytes: bytes_in,
             depth: d,
             is_dir: b,
+            count,
+            times: Times::default(),
+            info: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Fill in the size, entry count, times and metadata of this subtree, e.g. once a directory
+    /// has been read.
+    pub fn with_totals(mut self, totals: Totals) -> NamePair {
+        self.bytes = totals.size;
+        self.count = totals.count;
+        self.times = totals.times;
+        self.info = totals.own;
+        self
+    }
+
+    /// How far below the directory that was read this entry is. Entries directly inside it have
+    /// depth 1.
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Whether this is a directory rather than a file.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// The entries directly inside this directory. Empty for files and for directories whose
+    /// contents were not read (e.g. because they are beyond the maximum depth).
+    pub fn children(&self) -> &[NamePair] {
+        &self.children
+    }
+
+    /// Sort the contents of this directory, and of every directory below it, from largest to
+    /// smallest.
+    pub fn sort_children(&mut self) {
+        self.children.sort_by(|a, b| sort_by_size(b, a));
+        for c in &mut self.children {
+            c.sort_children();
+        }
+    }
+
+    fn find(&self, path: &Path) -> Option<&NamePair> {
+        if self.name == path {
+            Some(self)
+        } else {
+            self.children.iter().filter_map(|c| c.find(path)).next()
+        }
+    }
+
+    // The fields that describe this entry in machine-readable output, apart from its path.
+    fn fields(&self, opts: &DisplayOptions) -> Vec<(&'static str, String)> {
+        let mut out = vec![
+            ("bytes", self.bytes.get().to_string()),
+            ("depth", self.depth.to_string()),
+            ("is_dir", self.is_dir.to_string()),
+        ];
+        out.append(&mut extra_fields(opts, self.count, self.times));
+        out
+    }
+
+    fn to_json(&self, opts: &DisplayOptions) -> String {
+        format!(
+            "{{\"path\":{},\"bytes\":{},\"depth\":{},\"is_dir\":{}{}}}",
+            json_string(&self.name.to_string_lossy()),
+            self.bytes.get(),
+            self.depth,
+            self.is_dir,
+            json_fields(opts, self.count, self.times)
+        )
+    }
+
+    // NUL-delimited records carry the path exactly as it is on disk, since they are meant for
+    // other programs; the other formats are text, so invalid unicode is replaced.
+    fn to_record(&self, opts: &DisplayOptions) -> Vec<u8> {
+        let values = self
+            .fields(opts)
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect::<Vec<String>>();
+        match opts.format {
+            OutputFormat::Csv => format!(
+                "{},{}\n",
+                values.join(","),
+                csv_field(&self.name.to_string_lossy())
+            )
+            .into_bytes(),
+            OutputFormat::Null => {
+                let mut out = format!("{}\t", self.bytes.get()).into_bytes();
+                out.extend_from_slice(&path_bytes(&self.name));
+                out.push(b'\0');
+                out
+            }
+            _ => format!(
+                "{}\t{}\n",
+                values.join("\t"),
+                tsv_field(&self.name.to_string_lossy())
+            )
+            .into_bytes(),
         }
     }
 }
 
+/// The result of reading a directory: its total size, and the entries directly inside it.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FileTree {
     pub file_size: FileSize,
+    pub count: EntryCount,
+    pub times: Times,
+    /// The metadata of the directory that was read, if it could be read.
+    pub info: Option<FileInfo>,
     files: Vec<NamePair>,
 }
 
-pub fn display_item(name: &str, bytes: FileSize) {
-    if bytes != FileSize::new(0) {
-        let to_formatted = format!("{}", bytes);
-        println!("{}\t {}", &to_formatted.green(), name);
+/// Width of the bars drawn with `--bars`.
+const BAR_WIDTH: usize = 20;
+
+// The optional fields in machine-readable output, for an entry or for the directory that was
+// read.
+fn extra_fields(
+    opts: &DisplayOptions,
+    count: EntryCount,
+    times: Times,
+) -> Vec<(&'static str, String)> {
+    let mut out = Vec::new();
+    if opts.counts {
+        out.push(("files", count.files.to_string()));
+        out.push(("dirs", count.dirs.to_string()));
+        out.push(("symlinks", count.symlinks.to_string()));
+    }
+    if opts.time.is_some() {
+        out.push(("modified", times.modified.to_string()));
+        out.push(("accessed", times.accessed.to_string()));
+    }
+    out
+}
+
+// The optional fields as JSON, each with a leading comma.
+fn json_fields(opts: &DisplayOptions, count: EntryCount, times: Times) -> String {
+    extra_fields(opts, count, times)
+        .into_iter()
+        .map(|(k, v)| format!(",\"{}\":{}", k, v))
+        .collect()
+}
+
+// The header row for CSV output.
+fn csv_header(opts: &DisplayOptions) -> String {
+    let mut names = vec!["bytes", "depth", "is_dir"];
+    names.extend(
+        extra_fields(opts, EntryCount::default(), Times::default())
+            .into_iter()
+            .map(|(k, _)| k),
+    );
+    names.push("path");
+    format!("{}\n", names.join(","))
+}
+
+fn item_line(entry: &NamePair, total: Option<FileSize>, opts: &DisplayOptions) -> String {
+    let to_formatted = entry.bytes.format(opts.units);
+    let mut name = String::new();
+    if opts.counts {
+        name.push_str(&format!("{:>7} ", entry.count.total()));
+    }
+    if let Some(kind) = opts.time {
+        name.push_str(&format!("{} ", date(entry.times.get(kind))));
+    }
+    name.push_str(&entry.name.display().to_string());
+    let bytes = entry.bytes;
+    match total {
+        Some(t) => {
+            let fraction = if t.get() == 0 {
+                0.0
+            } else {
+                bytes.get() as f64 / t.get() as f64
+            };
+            format!(
+                "{}\t {} {:>3.0}% {}",
+                &to_formatted.green(),
+                bar(fraction, BAR_WIDTH).cyan(),
+                fraction * 100.0,
+                name
+            )
+        }
+        None => format!("{}\t {}", &to_formatted.green(), name),
+    }
+}
+
+/// Print a single entry. If a total is given, a bar showing the entry's share of it is drawn as
+/// well.
+pub fn display_item(entry: &NamePair, total: Option<FileSize>, opts: &DisplayOptions) {
+    if entry.bytes != FileSize::new(0) {
+        println!("{}", item_line(entry, total, opts));
     }
 }
 
@@ -69,58 +578,40 @@ impl Default for FileTree {
 }
 
 impl FileTree {
+    /// Filter the tree and sort what is left by size. Since this compares entries at all depths
+    /// against each other, the result is flat: every entry is at the top level, with no children.
     pub fn sort(
-        mut self,
+        self,
         maybe_num: Option<usize>,
         min_bytes: Option<u64>,
         dirs_only: bool,
         max_depth: Option<u8>,
     ) -> FileTree {
-        let self_size = if Some(self.file_size) > min_bytes.map(FileSize::new) {
-            self.file_size
-        } else {
-            FileSize::new(0)
-        };
+        let filtered = self.filtered(min_bytes, dirs_only, max_depth);
+        let mut files = Vec::new();
+        flatten_into(filtered.files, &mut files);
 
-        // filter by depth & truncate
+        // sort & truncate
         if let Some(n) = maybe_num {
-            self.files.sort_by(|a, b| sort_by_size(b, a));
-            let new = self
-                .files
-                .into_iter()
-                .filter(|a| {
-                    (if dirs_only { a.is_dir } else { true })
-                        && Some(a.bytes) > min_bytes.map(FileSize::new)
-                        && (max_depth.is_none() || Some(a.depth) <= max_depth)
-                })
-                .take(n)
-                .collect::<Vec<NamePair>>();
-            FileTree {
-                file_size: self_size,
-                files: new,
-            }
+            files.sort_by(|a, b| sort_by_size(b, a));
+            files.truncate(n);
         }
-        // sort by size and filter by depth
+        // sort by size
         else {
-            self.files.sort_by(|a, b| sort_by_size(a, b));
-            let new = self
-                .files
-                .into_iter()
-                .filter(|a| {
-                    (if dirs_only { a.is_dir } else { true })
-                        && Some(a.bytes) > min_bytes.map(FileSize::new)
-                        && (max_depth.is_none() || Some(a.depth) <= max_depth)
-                })
-                .collect::<Vec<NamePair>>();
-            FileTree {
-                file_size: self_size,
-                files: new,
-            }
+            files.sort_by(sort_by_size);
+        }
+
+        FileTree {
+            file_size: filtered.file_size,
+            count: filtered.count,
+            times: filtered.times,
+            info: filtered.info,
+            files,
         }
     }
 
     pub fn filtered(
-        mut self,
+        self,
         min_bytes: Option<u64>,
         dirs_only: bool,
         max_depth: Option<u8>,
@@ -131,25 +622,65 @@ impl FileTree {
             FileSize::new(0)
         };
 
-        self.files = self
-            .files
-            .into_iter()
-            .filter(|a| {
-                (if dirs_only { a.is_dir } else { true })
-                    && Some(a.bytes) > min_bytes.map(FileSize::new)
-                    && (max_depth.is_none() || Some(a.depth) <= max_depth)
-            })
-            .collect::<Vec<NamePair>>();
-
         FileTree {
             file_size: self_size,
-            files: self.files,
+            count: self.count,
+            times: self.times,
+            info: self.info,
+            files: prune(self.files, min_bytes, dirs_only, max_depth),
+        }
+    }
+
+    /// Sort the contents of every directory in the tree from largest to smallest, keeping the
+    /// hierarchy intact.
+    pub fn sort_children(&mut self) {
+        self.files.sort_by(|a, b| sort_by_size(b, a));
+        for c in &mut self.files {
+            c.sort_children();
+        }
+    }
+
+    /// The entries directly inside the directory that was read.
+    pub fn children(&self) -> &[NamePair] {
+        &self.files
+    }
+
+    /// Look up an entry anywhere in the tree by its path, e.g. to expand a single directory.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&NamePair> {
+        self.files
+            .iter()
+            .filter_map(|c| c.find(path.as_ref()))
+            .next()
+    }
+
+    /// All entries in the tree, each directory coming right after its contents.
+    pub fn entries(&self) -> Vec<&NamePair> {
+        let mut out = Vec::new();
+        walk_post_order(&self.files, &mut out);
+        out
+    }
+
+    /// Turn the tree into an entry for the directory it was read from, e.g. to attach it to a
+    /// larger tree.
+    pub fn into_entry(self, path: PathBuf, depth: u8) -> NamePair {
+        NamePair {
+            name: path,
+            bytes: self.file_size,
+            depth,
+            is_dir: true,
+            count: self.count,
+            times: self.times,
+            info: self.info,
+            children: self.files,
         }
     }
 
     pub fn new() -> FileTree {
         FileTree {
             file_size: FileSize::new(0),
+            count: EntryCount::default(),
+            times: Times::default(),
+            info: None,
             files: Vec::new(),
         }
     }
@@ -158,9 +689,34 @@ impl FileTree {
         self.file_size.add(size);
     }
 
+    /// Add an entry without adding its size to the total. This is used to gather up results that
+    /// were computed elsewhere, e.g. in another thread.
+    pub fn push_entry(&mut self, entry: NamePair) {
+        self.files.push(entry);
+    }
+
+    /// Move the entries of another tree into this one, leaving the totals alone.
+    pub fn append(&mut self, mut other: FileTree) {
+        self.files.append(&mut other.files);
+    }
+
+    /// Add an entry that was read after its contents, as `scan_each` does. Any entries already in
+    /// the tree that are deeper than a new directory are taken to be its contents. The total is
+    /// left alone.
+    pub fn push_post_order(&mut self, mut entry: NamePair) {
+        if entry.is_dir {
+            let mut n = self.files.len();
+            while n > 0 && self.files[n - 1].depth > entry.depth {
+                n -= 1;
+            }
+            entry.children = self.files.split_off(n);
+        }
+        self.files.push(entry);
+    }
+
     pub fn push(
         &mut self,
-        path: String,
+        path: PathBuf,
         size: FileSize,
         subtree: Option<&mut FileTree>,
         depth: u8,
@@ -169,29 +725,337 @@ impl FileTree {
         // add to total
         self.file_size.add(size);
 
-        // append subtree if appropriate
+        // attach subtree if appropriate
+        let mut entry = NamePair::new(path, size, depth, is_dir);
         if let Some(s) = subtree {
-            self.files.append(&mut s.files);
+            entry.count = s.count;
+            entry.times = s.times;
+            entry.info = s.info;
+            entry.children.append(&mut s.files);
         }
 
-        // return new file tree
-        self.files.push(NamePair::new(path, size, depth, is_dir));
+        self.count.add(entry.count);
+        self.times.add(entry.times);
+        self.files.push(entry);
     }
 
-    pub fn display_tree(&mut self, init_dir: &PathBuf) {
-        // display stuff
-        let vec = &self.files;
-        for name_pair in vec {
+    /// Print the tree using the given output format.
+    pub fn display(&mut self, init_dir: &Path, opts: &DisplayOptions) {
+        match opts.format {
+            OutputFormat::Human => self.display_tree(init_dir, opts),
+            OutputFormat::Json => println!("{}", self.to_json(init_dir, opts)),
+            _ => print_bytes(&self.to_records(init_dir, opts)),
+        }
+    }
+
+    /// Render the tree as CSV, TSV or NUL-delimited records of the form `bytes<TAB>path`. CSV
+    /// and TSV records end with the directory total at depth 0; NUL-delimited output leaves it
+    /// out. The size has to be stripped before passing paths on to `xargs -0`, e.g. with
+    /// `cut -z -f2`.
+    pub fn to_records(&self, init_dir: &Path, opts: &DisplayOptions) -> Vec<u8> {
+        let mut out = Vec::new();
+        if opts.format == OutputFormat::Csv {
+            out.extend_from_slice(csv_header(opts).as_bytes());
+        }
+        for name_pair in self.entries() {
             if name_pair.bytes != FileSize::new(0) {
-                let to_formatted = format!("{}", name_pair.bytes);
-                println!("{}\t {}", &to_formatted.green(), name_pair.name);
+                out.extend(name_pair.to_record(opts));
             }
         }
+        if opts.format != OutputFormat::Null && self.file_size != FileSize::new(0) {
+            out.extend(self.root(init_dir).to_record(opts));
+        }
+        out
+    }
+
+    // An entry standing for the directory that was read.
+    fn root(&self, init_dir: &Path) -> NamePair {
+        NamePair::new(init_dir.to_path_buf(), self.file_size, 0, true).with_totals(self.totals())
+    }
+
+    /// The size, entry count and times of the whole tree.
+    pub fn totals(&self) -> Totals {
+        Totals {
+            size: self.file_size,
+            count: self.count,
+            times: self.times,
+            own: self.info,
+        }
+    }
+
+    /// Add the totals of something that was read, without adding an entry for it.
+    pub fn add_totals(&mut self, totals: Totals) {
+        self.file_size.add(totals.size);
+        self.count.add(totals.count);
+        self.times.add(totals.times);
+    }
+
+    /// The size of the root and of each directory in the tree, keyed by path relative to
+    /// `init_dir`. The root's path is empty.
+    pub fn dir_sizes(&self, init_dir: &Path) -> HashMap<PathBuf, FileSize> {
+        let mut out = self
+            .entries()
+            .into_iter()
+            .filter(|e| e.is_dir)
+            .map(|e| {
+                let path = e.name.strip_prefix(init_dir).unwrap_or(&e.name);
+                (path.to_path_buf(), e.bytes)
+            })
+            .collect::<HashMap<_, _>>();
+        out.insert(PathBuf::new(), self.file_size);
+        out
+    }
+
+    /// Render the tree as a single JSON object. Entries that would not be shown in the human
+    /// output (those with a size of zero) are omitted.
+    pub fn to_json(&self, init_dir: &Path, opts: &DisplayOptions) -> String {
+        let entries = self
+            .entries()
+            .into_iter()
+            .filter(|a| a.bytes != FileSize::new(0))
+            .map(|a| a.to_json(opts))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"root\":{},\"total\":{}{},\"entries\":[{}]}}",
+            json_string(&init_dir.display().to_string()),
+            self.file_size.get(),
+            json_fields(opts, self.count, self.times),
+            entries
+        )
+    }
+
+    /// Drop entries that don't pass an age filter. Since a directory's times are those of the
+    /// newest thing in it, a directory can be dropped while some of its contents are kept; those
+    /// then take its place.
+    pub fn filter_age(self, age: &AgeFilter) -> FileTree {
+        if age.is_empty() {
+            return self;
+        }
+        FileTree {
+            files: prune_age(self.files, age),
+            ..self
+        }
+    }
 
+    /// Lay out the tree with each directory's contents indented below it, largest first. Each
+    /// line is returned along with the size to print next to it.
+    pub fn to_indented(&self, init_dir: &Path) -> Vec<(FileSize, String)> {
+        let mut out = Vec::new();
         if self.file_size != FileSize::new(0) {
-            let to_formatted = format!("{}", self.file_size);
-            let path = init_dir.display();
-            println!("{}\t {}", &to_formatted.green(), path);
+            out.push((self.file_size, init_dir.display().to_string()));
+            indent_into(&self.files, self.file_size, "", &mut out);
+        }
+        out
+    }
+
+    /// Print the tree with each directory's contents indented below it, like `tree`.
+    pub fn display_indented(&self, init_dir: &Path, units: Units) {
+        for (bytes, line) in self.to_indented(init_dir) {
+            let to_formatted = bytes.format(units);
+            println!("{}\t {}", &to_formatted.green(), line);
+        }
+    }
+
+    /// Print every entry in the tree, followed by the total. With `bars`, each line also shows
+    /// the entry's share of the total.
+    pub fn display_tree(&mut self, init_dir: &Path, opts: &DisplayOptions) {
+        let total = if opts.bars {
+            Some(self.file_size)
+        } else {
+            None
+        };
+
+        // display stuff
+        for name_pair in self.entries() {
+            display_item(name_pair, total, opts);
+        }
+
+        display_item(&self.root(init_dir), total, opts);
+    }
+}
+
+/// Prints entries one at a time as they are read, rather than collecting them into a `FileTree`
+/// first. Memory use is then bounded by the depth of the tree, rather than the number of entries.
+/// Since the total is not known until the end, bars are not drawn.
+pub struct StreamPrinter {
+    opts: DisplayOptions,
+    min_bytes: Option<u64>,
+    dirs_only: bool,
+    max_depth: Option<u8>,
+    age: AgeFilter,
+    first: bool,
+}
+
+impl StreamPrinter {
+    /// Set up a printer and print any header that the output format needs.
+    pub fn new(
+        init_dir: &Path,
+        opts: &DisplayOptions,
+        min_bytes: Option<u64>,
+        dirs_only: bool,
+        max_depth: Option<u8>,
+        age: AgeFilter,
+    ) -> StreamPrinter {
+        match opts.format {
+            OutputFormat::Csv => print!("{}", csv_header(opts)),
+            OutputFormat::Json => print!(
+                "{{\"root\":{},\"entries\":[",
+                json_string(&init_dir.display().to_string())
+            ),
+            _ => {}
+        }
+        StreamPrinter {
+            opts: *opts,
+            min_bytes,
+            dirs_only,
+            max_depth,
+            age,
+            first: true,
+        }
+    }
+
+    /// Print a single entry, if it passes the filters.
+    pub fn print(&mut self, entry: &NamePair) {
+        if entry.bytes == FileSize::new(0)
+            || !keep(entry, self.min_bytes, self.dirs_only, self.max_depth)
+            || !self.age.matches(entry.times)
+        {
+            return;
+        }
+        match self.opts.format {
+            OutputFormat::Human => display_item(entry, None, &self.opts),
+            OutputFormat::Json => print!(
+                "{}{}",
+                if self.first { "" } else { "," },
+                entry.to_json(&self.opts)
+            ),
+            _ => print_bytes(&entry.to_record(&self.opts)),
+        }
+        self.first = false;
+    }
+
+    /// Print the total for the directory that was read.
+    pub fn finish(self, init_dir: &Path, mut totals: Totals) {
+        if Some(totals.size) <= self.min_bytes.map(FileSize::new) {
+            totals.size = FileSize::new(0);
+        }
+        let root = NamePair::new(init_dir.to_path_buf(), totals.size, 0, true).with_totals(totals);
+        match self.opts.format {
+            OutputFormat::Human => display_item(&root, None, &self.opts),
+            OutputFormat::Json => println!(
+                "],\"total\":{}{}}}",
+                totals.size.get(),
+                json_fields(&self.opts, totals.count, totals.times)
+            ),
+            OutputFormat::Null => {}
+            _ => {
+                if totals.size != FileSize::new(0) {
+                    print_bytes(&root.to_record(&self.opts))
+                }
+            }
+        }
+    }
+}
+
+// An entry in a `TopN`, ranked by size and then by the order in which it was read, so that ties
+// come out the same way a stable sort would leave them.
+struct Ranked {
+    seq: usize,
+    entry: NamePair,
+}
+
+impl Ranked {
+    fn key(&self) -> (FileSize, Reverse<usize>) {
+        (self.entry.bytes, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Ranked) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Ranked) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Ranked) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Keeps the `n` largest entries seen so far, so that finding the largest entries in a tree takes
+/// memory proportional to `n` rather than to the size of the tree. Entries are filtered the same
+/// way as in `FileTree::sort`, and then by age.
+pub struct TopN {
+    n: usize,
+    min_bytes: Option<u64>,
+    dirs_only: bool,
+    max_depth: Option<u8>,
+    age: AgeFilter,
+    seen: usize,
+    heap: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl TopN {
+    pub fn new(
+        n: usize,
+        min_bytes: Option<u64>,
+        dirs_only: bool,
+        max_depth: Option<u8>,
+        age: AgeFilter,
+    ) -> TopN {
+        TopN {
+            n,
+            min_bytes,
+            dirs_only,
+            max_depth,
+            age,
+            seen: 0,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    /// Offer an entry, dropping the smallest one kept if there are now more than `n`.
+    pub fn push(&mut self, entry: NamePair) {
+        if self.n == 0
+            || !keep(&entry, self.min_bytes, self.dirs_only, self.max_depth)
+            || !self.age.matches(entry.times)
+        {
+            return;
+        }
+        self.seen += 1;
+        self.heap.push(Reverse(Ranked {
+            seq: self.seen,
+            entry,
+        }));
+        if self.heap.len() > self.n {
+            self.heap.pop();
+        }
+    }
+
+    /// Turn the entries kept into a (flat) `FileTree`, largest first.
+    pub fn into_tree(self, totals: Totals) -> FileTree {
+        let file_size = if Some(totals.size) > self.min_bytes.map(FileSize::new) {
+            totals.size
+        } else {
+            FileSize::new(0)
+        };
+        let mut ranked = self.heap.into_vec();
+        ranked.sort();
+        FileTree {
+            file_size,
+            count: totals.count,
+            times: totals.times,
+            info: totals.own,
+            files: ranked.into_iter().map(|r| r.0.entry).collect(),
         }
     }
 }
@@ -205,48 +1069,6 @@ impl fmt::Debug for FileSize {
 
 impl fmt::Display for FileSize {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.size < 1024 {
-            let pre_size = format!("{}", self.size);
-            write!(f, "{} b", &pre_size.pad_to_width(4))
-        } else if self.size < 1048576 {
-            let pre_size = if self.size / 1024 > 9 {
-                format!("{}", self.size / 1024)
-            } else if self.size as f32 / 1024.0 >= 9.95 {
-                format!("{:.0}", self.size as f32 / 1024.0)
-            } else {
-                format!("{:.1}", self.size as f32 / 1024.0)
-            };
-            write!(f, "{} kB", &pre_size.pad_to_width(4))
-        } else if self.size < 1073741824 {
-            // 2^30
-            let pre_size = if self.size / 1048576 > 9 {
-                format!("{}", self.size / 1048576)
-            } else if self.size as f32 / 1048576.0 >= 9.95 {
-                format!("{:.0}", self.size as f32 / 1048576.0)
-            } else {
-                format!("{:.1}", self.size as f32 / 1048576.0)
-            };
-            write!(f, "{} MB", &pre_size.pad_to_width(4))
-        } else if self.size < 1099511627776 {
-            // 2^40
-            let pre_size = if self.size / 1073741824 > 9 {
-                format!("{}", self.size / 1073741824)
-            } else if self.size as f32 / 1073741824.0 >= 9.95 {
-                format!("{:.0}", self.size as f32 / 1073741824.0)
-            } else {
-                format!("{:.1}", self.size as f32 / 1073741824.0)
-            };
-            write!(f, "{} GB", &pre_size.pad_to_width(4))
-        } else {
-            // for 1 TB and above
-            let pre_size = if self.size / 1099511627776 > 9 {
-                format!("{}", self.size / 1099511627776)
-            } else if self.size as f32 / 1099511627776.0 >= 9.95 {
-                format!("{:.0}", self.size as f32 / 1099511627776.0)
-            } else {
-                format!("{:.1}", self.size as f32 / 1099511627776.0)
-            };
-            write!(f, "{} TB", &pre_size.pad_to_width(4))
-        }
+        f.write_str(&self.format(Units::default()))
     }
 }
@@ -1,16 +1,29 @@
 extern crate num_cpus;
 
 use self::num_cpus::get;
+use cache::FileInfo;
+use error::{Internal, Warnings};
 use gitignore::*;
-use regex::RegexSet;
-use std::fs::File;
+use regex::bytes::RegexSet;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
 use std::fs::Metadata;
+use std::fs::{DirEntry, File, FileType};
+use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
 
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
 #[cfg(any(
     target_os = "macos",
     target_os = "freebsd",
@@ -49,38 +62,254 @@ pub fn size(m: &Metadata, blocks: bool) -> u64 {
     }
 }
 
+/// Get the device and inode numbers for a file, so that it can be identified across hard links
+/// and mount points.
+#[cfg(target_os = "linux")]
+pub fn dev_ino(m: &Metadata) -> (u64, u64) {
+    (m.st_dev(), m.st_ino())
+}
+
+#[cfg(any(target_os = "windows", target_os = "redox"))]
+pub fn dev_ino(_: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    target_os = "solaris"
+))]
+pub fn dev_ino(m: &Metadata) -> (u64, u64) {
+    (m.dev(), m.ino())
+}
+
+/// Get the device a path is on, e.g. to stay on one filesystem.
+pub fn device(p: &Path) -> Option<u64> {
+    fs::metadata(p).ok().map(|m| dev_ino(&m).0)
+}
+
+/// Whether a path is on a different device than `device`, e.g. because it is a mount point. This
+/// is always false when `device` is `None`.
+pub fn on_other_device(p: &Path, device: Option<u64>) -> bool {
+    match device {
+        Some(d) => self::device(p).is_some_and(|dev| dev != d),
+        None => false,
+    }
+}
+
+/// Get the number of hard links pointing to a file.
+#[cfg(target_os = "linux")]
+pub fn nlink(m: &Metadata) -> u64 {
+    m.st_nlink()
+}
+
+#[cfg(any(target_os = "windows", target_os = "redox"))]
+pub fn nlink(_: &Metadata) -> u64 {
+    1
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    target_os = "solaris"
+))]
+pub fn nlink(m: &Metadata) -> u64 {
+    m.nlink()
+}
+
+/// Files with more than one hard link that have been seen so far, by device and inode, so that
+/// each one is only counted once. This can be shared between threads.
+#[derive(Debug)]
+pub struct HardLinks {
+    seen: Option<Mutex<HashSet<(u64, u64)>>>,
+    every_file: bool,
+}
+
+impl HardLinks {
+    /// With `count_links`, every link to a file is counted, as if it were a separate file. When
+    /// following symlinks, a file can be reached more than once without having several links, so
+    /// every file is tracked.
+    pub fn new(count_links: bool, follow_symlinks: bool) -> HardLinks {
+        HardLinks {
+            seen: if count_links {
+                None
+            } else {
+                Some(Mutex::new(HashSet::new()))
+            },
+            every_file: follow_symlinks,
+        }
+    }
+
+    /// Whether every link to a file is counted.
+    pub fn counts_every_link(&self) -> bool {
+        self.seen.is_none()
+    }
+
+    /// Whether a file should be counted, i.e. whether this is the first link to it we have seen.
+    pub fn first_link(&self, m: &Metadata) -> bool {
+        self.first(dev_ino(m), nlink(m))
+    }
+
+    /// Same as `first_link`, for metadata that may have come from a `DirCache`.
+    pub fn first_link_info(&self, info: &FileInfo) -> bool {
+        self.first((info.dev, info.ino), info.nlink)
+    }
+
+    fn first(&self, id: (u64, u64), nlink: u64) -> bool {
+        match self.seen {
+            Some(ref seen) if self.every_file || nlink > 1 => match seen.lock() {
+                Ok(mut s) => s.insert(id),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+}
+
+/// Directories read so far when following symlinks, by device and inode, so that a link back up
+/// the tree doesn't send us around in circles, and a directory linked to twice is only counted
+/// once. This can be shared between threads.
+#[derive(Debug)]
+pub struct Symlinks {
+    visited: Option<Mutex<HashSet<(u64, u64)>>>,
+}
+
+impl Symlinks {
+    pub fn new(follow: bool) -> Symlinks {
+        Symlinks {
+            visited: if follow {
+                Some(Mutex::new(HashSet::new()))
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn follow(&self) -> bool {
+        self.visited.is_some()
+    }
+
+    /// Whether to read a directory, i.e. whether this is the first time we have come across it.
+    /// This is always true when not following symlinks, since each directory can then only be
+    /// reached once.
+    pub fn first_visit(&self, p: &Path) -> bool {
+        match self.visited {
+            Some(ref visited) => match (fs::metadata(p), visited.lock()) {
+                (Ok(m), Ok(mut v)) => v.insert(dev_ino(&m)),
+                _ => true,
+            },
+            None => true,
+        }
+    }
+
+    /// The type of a directory entry, looking through it if it is a symlink we are following.
+    /// Broken links are left as they are.
+    pub fn file_type(&self, val: &DirEntry) -> io::Result<FileType> {
+        let t = val.file_type()?;
+        if t.is_symlink() && self.follow() {
+            Ok(fs::metadata(val.path()).map(|m| m.file_type()).unwrap_or(t))
+        } else {
+            Ok(t)
+        }
+    }
+
+    /// The metadata of a directory entry, or of what it points to if it is a symlink we are
+    /// following.
+    pub fn metadata(&self, val: &DirEntry) -> io::Result<Metadata> {
+        if self.follow() && val.file_type()?.is_symlink() {
+            fs::metadata(val.path())
+        } else {
+            val.metadata()
+        }
+    }
+}
+
+/// Get the bytes of a path (or file name) to match against a regular expression. On Unix this is
+/// the path exactly as the filesystem has it, whether or not it is valid unicode; elsewhere,
+/// invalid sequences are replaced.
+#[cfg(unix)]
+pub fn path_bytes<P: AsRef<OsStr> + ?Sized>(p: &P) -> Cow<'_, [u8]> {
+    Cow::Borrowed(p.as_ref().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes<P: AsRef<OsStr> + ?Sized>(p: &P) -> Cow<'_, [u8]> {
+    match p.as_ref().to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// Turn the bytes of a path, as given by `path_bytes`, back into a path.
+#[cfg(unix)]
+pub fn path_from_bytes(b: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(b))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(b: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(b).into_owned())
+}
+
+/// Parse a field of a record in one of the files `sn` writes, such as a snapshot or a cache.
+pub(crate) fn parse_field<T: FromStr>(field: Option<&[u8]>) -> Option<T> {
+    str::from_utf8(field?).ok()?.parse().ok()
+}
+
 /// Gather the information from `.gitignore`, `.ignore`, and darcs `boring` files in a given
-/// directory, and assemble a `RegexSet` from it.
-pub fn mk_ignores(in_paths: &PathBuf, maybe_ignore: &Option<RegexSet>) -> Option<RegexSet> {
+/// directory, and assemble a `RegexSet` from it. A file that can't be read or parsed is warned
+/// about and ignores nothing.
+pub fn mk_ignores(
+    in_paths: &Path,
+    maybe_ignore: &Option<RegexSet>,
+    warnings: &Warnings,
+) -> Option<RegexSet> {
+    let or_empty = |set: Result<RegexSet, Internal>| {
+        set.unwrap_or_else(|e| {
+            warnings.note(e);
+            RegexSet::empty()
+        })
+    };
+    let read = |file: &mut File, path: &Path| {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map(|_| contents)
+            .map_err(|e| Internal::Io {
+                path: path.to_path_buf(),
+                source: e,
+            })
+    };
     if let Some(ref ignore) = *maybe_ignore {
         Some(ignore.to_owned())
     } else if let (ignore_path, Ok(mut file)) = {
-        let mut ignore_path = in_paths.clone();
+        let mut ignore_path = in_paths.to_path_buf();
         ignore_path.push(".ignore");
         (ignore_path.clone(), File::open(ignore_path))
     } {
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("File read failed."); // ok because we check that the file exists
-        Some(file_contents_to_regex(&contents, &ignore_path))
+        Some(or_empty(
+            read(&mut file, &ignore_path).and_then(|c| file_contents_to_regex(&c, &ignore_path)),
+        ))
     } else if let (gitignore_path, Ok(mut file)) = {
-        let mut gitignore_path = in_paths.clone();
+        let mut gitignore_path = in_paths.to_path_buf();
         gitignore_path.push(".gitignore");
         (gitignore_path.clone(), File::open(gitignore_path))
     } {
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("File read failed."); // ok because we check that the file exists
-        Some(file_contents_to_regex(&contents, &gitignore_path))
+        Some(or_empty(
+            read(&mut file, &gitignore_path)
+                .and_then(|c| file_contents_to_regex(&c, &gitignore_path)),
+        ))
     } else if let (darcs_path, Ok(mut file)) = {
-        let mut darcs_path = in_paths.clone();
+        let mut darcs_path = in_paths.to_path_buf();
         darcs_path.push("_darcs/prefs/boring");
         (darcs_path.clone(), File::open(darcs_path))
     } {
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("File read failed."); // ok because we check that the file exists
-        Some(darcs_contents_to_regex(&contents, &darcs_path))
+        Some(or_empty(
+            read(&mut file, &darcs_path).and_then(|c| darcs_contents_to_regex(&c, &darcs_path)),
+        ))
     } else {
         None
     }
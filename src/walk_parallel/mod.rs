@@ -8,20 +8,21 @@ use self::crossbeam::deque::Pop;
 use self::crossbeam::deque::Steal;
 use self::crossbeam::deque::Worker;
 use self::walkdir::WalkDir;
-use colored::*;
+use cache::{list_dir, DirCache, Kind};
 use error::*;
-use regex::{Regex, RegexSet};
+use output::{DisplayOptions, OutputFormat};
+use regex::bytes::{Regex, RegexSet};
 use std::ffi::OsStr;
 use std::fs;
+use std::mem;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::exit;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use types::FileSize;
-use utils::size;
+use types::{display_item, AgeFilter, FileSize, FileTree, NamePair, TimeKind, Totals, Units};
+use utils::{device, mk_ignores, on_other_device, path_bytes, size, HardLinks, Symlinks};
 
 pub use walk_parallel::single_threaded::*;
 
@@ -36,8 +37,7 @@ pub enum Status<T> {
 pub struct Walk {
     pub path: PathBuf,
     gitignore: Option<RegexSet>,
-    hgignore: Option<RegexSet>,
-    darcs_boring: Option<RegexSet>,
+    vimtags: bool,
     excludes: Option<Regex>,
     max_depth: Option<u8>,
     threshold: Option<u64>,
@@ -47,34 +47,94 @@ pub struct Walk {
     get_blocks: bool,
     follow_symlinks: bool,
     artifacts_only: bool,
+    links: Arc<HardLinks>,
+    symlinks: Arc<Symlinks>,
+    device: Option<u64>,
+    age: AgeFilter,
+    cache: Option<Arc<DirCache>>,
+    warnings: Arc<Warnings>,
+    collect: bool,
+    display: DisplayOptions,
 }
 
 impl Walk {
     /// function to make output from a 'Walk', using one thread. It also takes an 'Arc<AtomicU64>'
-    /// and will add the relevant directory sizes to it.
-    pub fn print_dir(w: &Walk, total: &Arc<AtomicUsize>) {
+    /// and will add the relevant directory sizes to it. Entry counts and times are added to
+    /// `collected`, and when the output format is not human-readable, so are the entries
+    /// themselves.
+    pub fn print_dir(w: &Walk, total: &Arc<AtomicUsize>, collected: &Arc<Mutex<FileTree>>) {
         let excludes = match w.excludes {
             Some(ref x) => Some(x),
             _ => None,
         };
 
-        let v = if excludes.is_some() || w.artifacts_only {
+        // a project directory counts as a whole in artifact mode, as it does when reading on one
+        // thread
+        let project_dir = w.artifacts_only
+            && w.path
+                .file_name()
+                .is_some_and(|name| is_project_dir(&w.path, name));
+
+        let v = if project_dir {
+            read_size(
+                &w.path,
+                excludes,
+                &w.gitignore,
+                w.vimtags,
+                false,
+                w.get_blocks,
+                &w.links,
+                w.device,
+                &w.symlinks,
+                w.cache.as_deref(),
+                &w.warnings,
+            )
+            .map(|totals| {
+                let mut tree = FileTree::new();
+                tree.add_totals(totals);
+                tree.info = totals.own;
+                tree
+            })
+        } else if excludes.is_some() || w.artifacts_only || w.cache.is_some() {
             read_all(
                 &w.path,
                 w.start_depth as u8,
                 w.max_depth,
                 excludes,
                 &w.gitignore,
-                false,
+                w.vimtags,
                 w.artifacts_only,
+                w.get_blocks,
+                &w.links,
+                w.device,
+                &w.symlinks,
+                w.cache.as_deref(),
+                &w.warnings,
             )
         } else {
-            read_all_fast(&w.path, w.start_depth as u8, w.max_depth)
+            read_all_fast(
+                &w.path,
+                w.start_depth as u8,
+                w.max_depth,
+                w.get_blocks,
+                &w.links,
+                w.device,
+                &w.symlinks,
+                &w.warnings,
+            )
+        };
+        let v = match v {
+            Ok(v) => v,
+            Err(e) => return w.warnings.warn(&e),
         };
 
         let subdir_size = v.file_size.get();
 
         total.fetch_add(subdir_size as usize, Ordering::Relaxed);
+        if let Ok(mut c) = collected.lock() {
+            c.count.add(v.count);
+            c.times.add(v.times);
+        }
 
         let mut to_print = if let Some(m) = w.threshold {
             subdir_size > m
@@ -88,9 +148,21 @@ impl Walk {
 
         if to_print {
             // filter by depth
-            let mut v_filtered = v.filtered(w.threshold, !w.show_files, w.max_depth);
+            let v_filtered = v.filtered(w.threshold, !w.show_files, w.max_depth);
+
+            // filter by age, which may drop this directory but keep some of its contents
+            let mut subtree = FileTree::new();
+            subtree.push_entry(v_filtered.into_entry(w.path.clone(), w.start_depth as u8));
+            let subtree = subtree.filter_age(&w.age);
 
-            v_filtered.display_tree(&w.path);
+            // entries are printed before the total is known, so there are no bars to draw
+            if w.prints() {
+                for entry in subtree.entries() {
+                    display_item(entry, None, &w.display);
+                }
+            } else if let Ok(mut c) = collected.lock() {
+                c.append(subtree);
+            }
         }
     }
 
@@ -114,16 +186,83 @@ impl Walk {
         self.show_files = true;
     }
 
-    /// include files when printing
+    /// follow symlinks to files and directories, reading each target once
+    pub fn follow_symlinks(&mut self) {
+        self.follow_symlinks = true;
+        self.links = Arc::new(HardLinks::new(self.links.counts_every_link(), true));
+        self.symlinks = Arc::new(Symlinks::new(true));
+    }
+
+    /// don't descend into directories on other filesystems than the one the walk starts on
+    pub fn one_file_system(&mut self) {
+        self.device = device(&self.path);
+    }
+
+    /// count every hard link to a file, rather than each file once
+    pub fn count_links(&mut self) {
+        self.links = Arc::new(HardLinks::new(true, self.follow_symlinks));
+    }
+
+    /// reuse the listings of directories that haven't changed since they were cached
+    pub fn set_cache(&mut self, cache: Arc<DirCache>) {
+        self.cache = Some(cache);
+    }
+
+    /// count problems that don't stop the walk here, rather than in a counter of its own
+    pub fn set_warnings(&mut self, warnings: Arc<Warnings>) {
+        self.warnings = warnings;
+    }
+
+    /// count disk usage (allocated blocks) rather than apparent sizes
     pub fn blocks(&mut self) {
         self.get_blocks = true;
     }
 
-    /// include files when printing
+    /// only count build artifacts
     pub fn artifacts_only(&mut self) {
         self.artifacts_only = true;
     }
 
+    /// set the paths to treat as ignored by version control in artifact mode
+    pub fn set_gitignore(&mut self, g: RegexSet) {
+        self.gitignore = Some(g);
+    }
+
+    /// count tags files generated for vim or emacs as artifacts
+    pub fn vimtags(&mut self) {
+        self.vimtags = true;
+    }
+
+    /// set the output format
+    pub fn set_format(&mut self, f: OutputFormat) {
+        self.display.format = f;
+    }
+
+    /// set the units to print file sizes in
+    pub fn set_units(&mut self, u: Units) {
+        self.display.units = u;
+    }
+
+    /// show how many files, directories and symlinks each entry holds
+    pub fn count_entries(&mut self) {
+        self.display.counts = true;
+    }
+
+    /// show when each entry was last modified or accessed
+    pub fn set_time(&mut self, t: Option<TimeKind>) {
+        self.display.time = t;
+    }
+
+    /// only show entries last modified (or accessed) within some window
+    pub fn set_age(&mut self, age: AgeFilter) {
+        self.age = age;
+    }
+
+    /// Whether entries are printed as they are read, rather than gathered up.
+    fn prints(&self) -> bool {
+        self.display.format == OutputFormat::Human && !self.collect
+    }
+
     fn get_proc(&self) -> usize {
         self.nproc
     }
@@ -134,8 +273,7 @@ impl Walk {
         Walk {
             path: p,
             gitignore: None,
-            hgignore: None,
-            darcs_boring: None,
+            vimtags: false,
             excludes: None,
             max_depth: None,
             threshold: None,
@@ -145,6 +283,14 @@ impl Walk {
             get_blocks: false,
             follow_symlinks: false,
             artifacts_only: false,
+            links: Arc::new(HardLinks::new(false, false)),
+            symlinks: Arc::new(Symlinks::new(false)),
+            device: None,
+            age: AgeFilter::default(),
+            cache: None,
+            warnings: Arc::new(Warnings::new()),
+            collect: false,
+            display: DisplayOptions::default(),
         }
     }
 
@@ -155,50 +301,72 @@ impl Walk {
     /// This takes a 'Walk' and a 'Worker<Status<Walk>>' and executes the walk *in parallel*,
     /// creating new work for each subdirectory. It's not the most efficient concurrency
     /// imaginable, but it's fast and easy-ish to use. It *also* takes in an 'Arc<AtomicU64>',
-    /// which it updates with any file sizes in the directory.
-    pub fn push_subdir(w: &Walk, worker: &mut Worker<Status<Walk>>, total: &Arc<AtomicUsize>) {
+    /// which it updates with any file sizes in the directory. Fails if the path can't be read
+    /// at all.
+    pub fn push_subdir(
+        w: &Walk,
+        worker: &mut Worker<Status<Walk>>,
+        total: &Arc<AtomicUsize>,
+        collected: &Arc<Mutex<FileTree>>,
+    ) -> Result<(), Internal> {
         let in_paths = &w.path;
+        let mut totals = Totals::default();
+        let gitignore = if w.artifacts_only {
+            mk_ignores(in_paths, &w.gitignore, &w.warnings)
+        } else {
+            None
+        };
+
+        // links back to the root shouldn't be followed
+        w.symlinks.first_visit(in_paths);
 
         // fill up queue + print out files
-        if let Ok(paths) = fs::read_dir(in_paths) {
+        let read = list_dir(in_paths, &w.symlinks, w.cache.as_deref(), &w.warnings);
+        if let Ok(listing) = read {
+            // count the directory itself
+            totals.count.dirs += 1;
+            totals.own = listing.own;
+            if let Some(own) = listing.own {
+                let own = own.dir_totals(w.get_blocks);
+                total.fetch_add(own.size.get() as usize, Ordering::Relaxed);
+                totals.add(own);
+            }
+
             // iterate over all the entries in the directory
-            for p in paths {
-                let val = match p {
-                    Ok(x) => x,
-                    _ => {
-                        eprintln!("{}: path error at {:?}.", "Error".red(), p);
-                        exit(0x0001)
-                    }
-                };
+            for child in listing.children {
+                let path = in_paths.join(&child.name);
 
                 let exclude_check = if let Some(ref x) = w.excludes {
-                    if let Some(r) = val.path().into_os_string().to_str() {
-                        !x.is_match(r)
-                    } else {
-                        eprintln!(
-                            "{}: ignoring invalid unicode at: {:?}",
-                            "Warning".yellow(),
-                            val.path().display()
-                        );
-                        true
-                    }
+                    !x.is_match(&path_bytes(&path))
                 } else {
                     true
                 };
 
                 if exclude_check {
-                    match val.file_type() {
-                        Ok(t) => {
-                            if t.is_dir() {
-                                let mut new_path = w.path.to_owned();
-                                new_path.push(val.file_name());
-                                let mut new_walk = Walk::new(new_path, w.get_proc());
+                    match child.kind {
+                        Kind::Other => w.warnings.warn(&Internal::UnknownFileType(path)),
+                        t => {
+                            if t.is_dir() && !on_other_device(&path, w.device) {
+                                let mut new_walk = Walk::new(path, w.get_proc());
                                 if w.excludes.is_some() {
                                     new_walk.set_regex(w.excludes.clone().unwrap());
                                 }
                                 if w.show_files {
                                     new_walk.with_files();
                                 }
+                                if w.get_blocks {
+                                    new_walk.blocks();
+                                }
+                                // share the hard links seen with the other threads
+                                new_walk.links = w.links.clone();
+                                new_walk.device = w.device;
+                                new_walk.follow_symlinks = w.follow_symlinks;
+                                new_walk.symlinks = w.symlinks.clone();
+                                new_walk.cache = w.cache.clone();
+                                new_walk.warnings = w.warnings.clone();
+                                new_walk.artifacts_only = w.artifacts_only;
+                                new_walk.gitignore = gitignore.clone();
+                                new_walk.vimtags = w.vimtags;
                                 new_walk.bump_depth();
                                 if let Some(d) = w.max_depth {
                                     new_walk.set_depth(d);
@@ -206,76 +374,91 @@ impl Walk {
                                 if let Some(b) = w.threshold {
                                     new_walk.set_threshold(b);
                                 }
+                                new_walk.age = w.age;
+                                new_walk.collect = w.collect;
+                                new_walk.display = w.display;
                                 worker.push(Status::Data(new_walk)); // pass a vector of Arc's to do 2-level traversals?
                             } else if t.is_file() {
-                                if let Ok(l) = val.metadata() {
-                                    let size = size(&l, w.get_blocks);
+                                if let Some(info) = child.info {
+                                    if w.artifacts_only
+                                        && !is_artifact(
+                                            &child.name,
+                                            &path,
+                                            info.mode,
+                                            w.vimtags,
+                                            &gitignore,
+                                        )
+                                        || !w.links.first_link_info(&info)
+                                    {
+                                        continue;
+                                    }
+                                    let size = info.size(w.get_blocks);
                                     total.fetch_add(size as usize, Ordering::Relaxed);
-                                    if w.show_files && size != 0 {
-                                        let to_formatted = format!("{}", FileSize::new(size));
-                                        println!(
-                                            "{}\t {}",
-                                            &to_formatted.green(),
-                                            val.path().display()
-                                        );
+                                    let file_totals = info.file_totals(w.get_blocks);
+                                    totals.add(file_totals);
+                                    if w.show_files && size != 0 && w.age.matches(file_totals.times)
+                                    {
+                                        let entry = NamePair::new(
+                                            path,
+                                            FileSize::new(size),
+                                            w.start_depth as u8 + 1,
+                                            false,
+                                        )
+                                        .with_totals(file_totals);
+                                        if w.prints() {
+                                            display_item(&entry, None, &w.display);
+                                        } else if let Ok(mut c) = collected.lock() {
+                                            c.push_entry(entry);
+                                        }
                                     }
                                 } else {
-                                    eprintln!(
-                                        "{}: could not find filesize for file at {}.",
-                                        "Warning".yellow(),
-                                        val.path().display()
-                                    );
+                                    w.warnings.warn(&Internal::PathDoesNotExist(path));
+                                }
+                            } else if t.is_symlink() && !w.artifacts_only {
+                                totals.count.symlinks += 1;
+                                if w.follow_symlinks {
+                                    w.warnings.warn(&Internal::BrokenSymlink(path));
                                 }
                             }
                         }
-                        _ => eprintln!(
-                            "{}: could not determine file type for: {}",
-                            "Warning".yellow(),
-                            val.path().display()
-                        ),
                     }
                 }
             }
-
-            // send "done" messages to all the workers
-            let iter = 0..(w.get_proc());
-            iter.map(|_| worker.push(Status::Done)).count();
         }
         // if we can't read the directory contents, figure out why
         // 1: check the path exists
         else if !in_paths.exists() {
-            eprintln!(
-                "{}: path '{}' does not exist, or you do not have permission to enter.",
-                "Error".red(),
-                &in_paths.display()
-            );
+            return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
         }
         // 2: check the path is actually a directory
         else if !in_paths.is_dir() {
             if w.artifacts_only {
-                eprintln!(
-                    "{}: {} is not a directory; not searching for artifacts",
-                    "Warning".yellow(),
-                    &in_paths.display()
-                );
+                w.warnings.note(format!(
+                    "{} is not a directory; not searching for artifacts",
+                    in_paths.display()
+                ));
             }
 
-            if let Ok(l) = in_paths.metadata() {
-                let size = size(&l, w.get_blocks); // l.len();
-                let to_formatted = format!("{}", FileSize::new(size));
-                println!("{}\t {}", &to_formatted.green(), in_paths.display());
-            } else {
-                panic!("{}", Internal::IoError);
-            }
+            let l = in_paths.metadata().map_err(|e| Internal::Io {
+                path: in_paths.to_path_buf(),
+                source: e,
+            })?;
+            let size = size(&l, w.get_blocks);
+            total.fetch_add(size as usize, Ordering::Relaxed);
+            totals.add(Totals::file(FileSize::new(size), &l));
         }
-        // 3: otherwise, give a warning about permissions
-        else {
-            eprintln!(
-                "{}: permission denied for directory: {}",
-                "Warning".yellow(),
-                &in_paths.display()
-            );
+        // 3: otherwise, warn about whatever stopped it being read
+        else if let Err(e) = read {
+            totals.count.dirs += 1;
+            w.warnings.warn(&Internal::from_io(in_paths, e));
+        }
+
+        if let Ok(mut c) = collected.lock() {
+            c.count.add(totals.count);
+            c.times.add(totals.times);
+            c.info = totals.own;
         }
+        Ok(())
     }
 }
 
@@ -285,7 +468,7 @@ fn ats_cgen(p: Option<&OsStr>) -> bool {
             Regex::new(r"(_(d|h|s)ats\.c|_lats\.dats|_sats\.c|_stub\.h)$").unwrap();
     }
     match p {
-        Some(p) => DATS_C.is_match(&p.to_string_lossy().to_string()),
+        Some(p) => DATS_C.is_match(&path_bytes(p)),
         None => false,
     }
 }
@@ -295,12 +478,8 @@ fn latex_log<P: AsRef<Path>>(p: P) -> bool {
         static ref LOG: Regex = Regex::new(r"\.log$").unwrap();
     }
 
-    if LOG.is_match(&p.as_ref().to_string_lossy().to_string()) {
-        let mut parent = (&p.as_ref())
-            .parent()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
+    if LOG.is_match(&path_bytes(p.as_ref())) {
+        let mut parent = p.as_ref().parent().unwrap().to_string_lossy().to_string();
         parent.push_str("/*.tex");
         glob_exists(&parent)
     } else {
@@ -308,9 +487,24 @@ fn latex_log<P: AsRef<Path>>(p: P) -> bool {
     }
 }
 
-// TODO figure out why the unwrap_or is failing?
+fn walk_error(e: walkdir::Error) -> Internal {
+    let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+    match e.into_io_error() {
+        Some(source) => Internal::from_io(&path, source),
+        // walkdir only reports a loop without an io::Error, and it doesn't follow symlinks here
+        None => Internal::UnknownFileType(path),
+    }
+}
+
+/// Remove build artifacts under `p`. Returns an error if `p` itself can't be read; directories
+/// below it that can't be read and artifacts that can't be removed are reported through `warnings`.
 // FIXME take optional reference to a regex
-pub fn clean_project_dirs<P: AsRef<Path>>(p: P, exclude: &Option<Regex>, _: bool) {
+pub fn clean_project_dirs<P: AsRef<Path>>(
+    p: P,
+    exclude: &Option<Regex>,
+    _: bool,
+    warnings: &Warnings,
+) -> Result<(), Internal> {
     lazy_static! {
         static ref REGEX: Regex =
             Regex::new(r"\.(a|i|ii|la|lo|o|keter|bc|dyn_o|d|rlib|crate|hi|hc|chi|dyn_hi|jsexe|webapp|js\.externs|ibc|toc|aux|fdb_latexmk|spl|bbl|blg|fls|egg-info|whl|js_a|js_hi|jld|ji|js_o|so.*|dump-.*|vmb|crx|orig|elmo|elmi|hspec-failures|pyc|mod|vo|beam|agdai|go\.(v|teak|xmldef|rewrittenast|rewrittengo|simplego|tree-(bind|eval|finish|parse))|p_hi|p_o|prof|hide-cache|ghc\.environment\..*\d.\d.\d|(t|p|m)ix|synctex\.gz|hl|sandbox\.config|hp|eventlog|ipa|ttc|chs\.h|chi|\d+\.actual|\d+\.expected)$")
@@ -320,50 +514,96 @@ pub fn clean_project_dirs<P: AsRef<Path>>(p: P, exclude: &Option<Regex>, _: bool
         static ref SRC_CONTROL: Regex = Regex::new(r"(_darcs|\.(git|hg|pijul|gnupg))").unwrap();
     }
 
-    for dir in WalkDir::new(p)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|p| {
-            exclude
-                .clone()
-                .map(|e| e.is_match(&p.path().to_string_lossy().to_string()))
-                != Some(false)
-        })
-        .filter(|p| !SRC_CONTROL.is_match(&p.path().to_string_lossy().to_string()))
-        .filter(|p| {
-            REGEX.is_match(&p.path().to_string_lossy().to_string())
-                || is_project_dir(
-                    &p.path().to_string_lossy().to_string(),
-                    &p.path()
-                        .file_name()
-                        .map(|x| x.to_string_lossy().to_string())
-                        .unwrap_or_else(|| "".to_string()),
-                )
-                || latex_log(&p.path())
-                || ats_cgen(p.path().file_name())
-                || ({
-                    let x = &p.path().to_string_lossy().to_string();
-                    x.ends_with("/flxg_stats.txt")
-                })
-        })
-    {
-        if dir.file_type().is_file() {
-            fs::remove_file(dir.path()).unwrap_or(());
+    let is_artifact = |p: &Path| {
+        REGEX.is_match(&path_bytes(p))
+            || is_project_dir(p, p.file_name().unwrap_or_else(|| OsStr::new("")))
+            || latex_log(p)
+            || ats_cgen(p.file_name())
+            || p.to_string_lossy().ends_with("/flxg_stats.txt")
+    };
+
+    let mut entries = WalkDir::new(p).into_iter();
+    while let Some(entry) = entries.next() {
+        let dir = match entry {
+            Ok(dir) => dir,
+            Err(e) => {
+                let root = e.depth() == 0;
+                let e = walk_error(e);
+                if root {
+                    return Err(e);
+                }
+                warnings.warn(&e);
+                continue;
+            }
+        };
+        let path = dir.path();
+        if exclude.as_ref().map(|e| e.is_match(&path_bytes(path))) == Some(false)
+            || SRC_CONTROL.is_match(&path_bytes(path))
+            || !is_artifact(path)
+        {
+            continue;
+        }
+        let removed = if dir.file_type().is_file() {
+            fs::remove_file(path)
         } else if dir.file_type().is_dir() {
-            fs::remove_dir_all(dir.path()).unwrap_or(());
+            // don't walk into the directory once it's gone
+            entries.skip_current_dir();
+            fs::remove_dir_all(path)
+        } else {
+            Ok(())
+        };
+        if let Err(source) = removed {
+            warnings.warn(&Internal::Remove {
+                path: path.to_path_buf(),
+                source,
+            });
         }
     }
+    Ok(())
 }
 
 /// Given a 'Walk' struct, traverse it concurrently and print out any relevant outputs.
 /// Currently, this only works for a depth of two, which is probably bad.
-pub fn print_parallel(w: Walk) {
+pub fn print_parallel(w: Walk) -> Result<(), Internal> {
+    let path_display = w.path.clone();
+    let display = w.display;
+    let (size, mut tree) = run_parallel(w)?;
+
+    // print directory total.
+    if display.format != OutputFormat::Human {
+        tree.file_size = size;
+        tree.display(&path_display, &display);
+    } else {
+        let root = NamePair::new(path_display, size, 0, true).with_totals(Totals {
+            size,
+            ..tree.totals()
+        });
+        display_item(&root, None, &display);
+    }
+    Ok(())
+}
+
+/// Given a 'Walk' struct, traverse it concurrently and gather everything it finds into a
+/// `FileTree` rather than printing it.
+pub fn collect_parallel(mut w: Walk) -> Result<FileTree, Internal> {
+    w.collect = true;
+    let (size, mut tree) = run_parallel(w)?;
+    tree.file_size = size;
+    Ok(tree)
+}
+
+/// Traverse a 'Walk' concurrently, returning the total size along with whatever was gathered
+/// up rather than printed.
+fn run_parallel(w: Walk) -> Result<(FileSize, FileTree), Internal> {
     // initialize the total at 0 and create a reference to it
     let val = AtomicUsize::new(0);
     let arc = Arc::new(val);
     let arc_producer = arc.clone();
     let arc_child = arc.clone();
-    let path_display = w.path.clone();
+
+    // entries gathered up for non-human output formats
+    let collected = Arc::new(Mutex::new(FileTree::new()));
+    let collected_producer = collected.clone();
 
     // set up worker & stealer
     let (mut worker, stealer) = fifo();
@@ -371,24 +611,28 @@ pub fn print_parallel(w: Walk) {
     // set up our iterator for the workers
     let iter = 0..(&w.get_proc() - 1);
 
-    let _ = (&w.path).is_file();
+    let _ = w.path.is_file();
 
     // create the producer in another thread
     let child_producer = thread::spawn(move || {
         let arc_local = arc_producer.clone();
 
-        // assign work to everyone
-        Walk::push_subdir(&w, &mut worker, &arc_local);
+        // assign work to everyone, then send "done" messages to all the workers
+        let pushed = Walk::push_subdir(&w, &mut worker, &arc_local, &collected_producer);
+        for _ in 0..w.get_proc() {
+            worker.push(Status::Done);
+        }
 
         // start popping off values in the worker's thread
         loop {
             if let Pop::Data(p) = worker.pop() {
                 match p {
-                    Status::Data(d) => Walk::print_dir(&d, &arc_local),
+                    Status::Data(d) => Walk::print_dir(&d, &arc_local, &collected_producer),
                     _ => break,
                 }
             }
         }
+        pushed
     });
 
     // create a vector of thread handles so that it doesn't execute
@@ -401,12 +645,13 @@ pub fn print_parallel(w: Walk) {
         let stealer_clone = stealer.clone();
 
         let arc_local = arc_child.clone();
+        let collected_local = collected.clone();
 
         // run the stealer in a new thread
         let child_consumer = thread::spawn(move || loop {
             if let Steal::Data(p) = stealer_clone.steal() {
                 match p {
-                    Status::Data(d) => Walk::print_dir(&d, &arc_local),
+                    Status::Data(d) => Walk::print_dir(&d, &arc_local, &collected_local),
                     _ => break,
                 }
             }
@@ -416,7 +661,7 @@ pub fn print_parallel(w: Walk) {
     }
 
     // join the child producer to the main thread
-    let _ = child_producer.join();
+    let pushed = child_producer.join();
 
     // join the workers to the main thread
     let _ = threads
@@ -435,9 +680,12 @@ pub fn print_parallel(w: Walk) {
     let m = arc.load(Ordering::SeqCst); // TODO - check if this works with Relaxed?
     let size = FileSize::new(m as u64);
 
-    // print directory total.
-    if size != FileSize::new(0) {
-        let to_formatted = format!("{}", size);
-        println!("{}\t {}", &to_formatted.green(), path_display.display());
+    let tree = match collected.lock() {
+        Ok(mut tree) => mem::take(&mut *tree),
+        Err(_) => FileTree::new(),
+    };
+    match pushed {
+        Ok(Err(e)) => Err(e),
+        _ => Ok((size, tree)),
     }
 }
@@ -1,24 +1,29 @@
 extern crate glob;
 
 use self::glob::glob;
-use colored::*;
+use cache::{list_dir, DirCache, FileInfo, Kind};
 use error::*;
-use regex::{Regex, RegexSet};
+use regex::bytes::{Regex, RegexSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::fs::Metadata;
-use std::path::PathBuf;
-use std::process::exit;
+use std::path::{Path, PathBuf};
 use std::result::Result;
 use types::*;
 use utils::*;
 
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::fs::PermissionsExt;
-
 pub fn glob_exists(s: &str) -> bool {
     glob(s).unwrap().filter_map(Result::ok).count() != 0 // ok because panic on IO Errors shouldn't happen.
 }
 
+/// Read a subdirectory, warning rather than failing if it can't be read so that the rest of the
+/// tree is still counted.
+pub(crate) fn or_warn<T: Default>(read: Result<T, Internal>, warnings: &Warnings) -> T {
+    read.unwrap_or_else(|e| {
+        warnings.warn(&e);
+        T::default()
+    })
+}
+
 /// Helper function to identify project directories. The heuristic is as follows:
 ///
 /// 1. For `.stack-work`, look for a `.cabal` file or a `package.yaml` file in the parent
@@ -32,17 +37,23 @@ pub fn glob_exists(s: &str) -> bool {
 ///    true.
 /// 7. In all other cases, return false, but still proceed into the directory to search files by
 ///    extension.
-pub fn is_project_dir(p: &str, name: &str) -> bool {
+pub fn is_project_dir(p: &Path, name: &OsStr) -> bool {
     // for project directories
     lazy_static! {
-        static ref REGEX_PROJECT_DIR: Regex =
-            Regex::new(r"_minted|((\.stack-work|build|gen|cbits|ats-deps|\.atspkg|target|\.reco-work|\.cabal-sandbox|dist|\.criterion|dist-newstyle.*|target|\.egg-info|elm-stuff|\.pulp-cache|\.psc-package|output|bower_components|node_modules|__pycache__|lib|\.liquid)$)")
+        static ref REGEX_PROJECT_DIR: ::regex::Regex =
+            ::regex::Regex::new(r"_minted|((\.stack-work|build|gen|cbits|ats-deps|\.atspkg|target|\.reco-work|\.cabal-sandbox|dist|\.criterion|dist-newstyle.*|target|\.egg-info|elm-stuff|\.pulp-cache|\.psc-package|output|bower_components|node_modules|__pycache__|lib|\.liquid)$)")
             .unwrap();
     }
 
+    // none of the names we look for are invalid unicode
+    let name = match name.to_str() {
+        Some(n) => n,
+        None => return false,
+    };
+
     if REGEX_PROJECT_DIR.is_match(name) {
-        let mut parent_path = PathBuf::from(p);
-        let mut parent_string = p.to_owned();
+        let mut parent_path = p.to_path_buf();
+        let mut parent_string = p.to_string_lossy().into_owned();
         match name {
             ".stack-work" => {
                 let mut hpack = parent_path.clone();
@@ -86,12 +97,12 @@ pub fn is_project_dir(p: &str, name: &str) -> bool {
             // parent_path.exists()
             // }
             "elm-stuff" => {
-                let mut package_path = PathBuf::from(p);
+                let mut package_path = p.to_path_buf();
                 package_path.push("../elm-package.json");
                 package_path.exists()
             }
             ".pulp-cache" | "output" | ".psc-package" => {
-                let mut package_path = PathBuf::from(p);
+                let mut package_path = p.to_path_buf();
                 package_path.push("../psc-package.json");
                 package_path.exists()
             }
@@ -111,7 +122,7 @@ pub fn is_project_dir(p: &str, name: &str) -> bool {
                     || glob_exists(&parent_string_idr2)
             }
             "bower_components" => {
-                let mut package_path = PathBuf::from(p);
+                let mut package_path = p.to_path_buf();
                 package_path.push("../bower.json");
                 package_path.exists()
             }
@@ -147,7 +158,7 @@ pub fn is_project_dir(p: &str, name: &str) -> bool {
 /// - `.c`: ATS
 /// - `.rlib`, `.crate`: rust
 /// - `.hi`, `.hc`, `.chi`, `.dyn_hi`, `.dyn_o`, `.p_hi`, `.p_o`, `.prof`, `.dump-.*`, `.tix`,
-/// `.mix`, `.pix`: GHC
+///   `.mix`, `.pix`: GHC
 /// - `.webapp`: Web app manifest
 /// - `.js.externs`, `.jsexe`, `.min.js`:
 /// - `.ibc`: Idris
@@ -167,9 +178,9 @@ pub fn is_project_dir(p: &str, name: &str) -> bool {
 /// - `.chs.h`, `.chi`: c2hs
 /// - `.1.expected`, `.1.actual`: Futhark test results
 pub fn is_artifact(
-    path_str: &str,
-    full_path: &str,
-    metadata: &Metadata,
+    name: &OsStr,
+    full_path: &Path,
+    mode: u32,
     vimtags: bool,
     gitignore: &Option<RegexSet>,
 ) -> bool {
@@ -186,96 +197,126 @@ pub fn is_artifact(
                 .unwrap();
         }
 
-        if REGEX.is_match(path_str) || (path_str == "tags" && vimtags) {
+        let name_bytes = path_bytes(name);
+        if REGEX.is_match(&name_bytes) || (name == "tags" && vimtags) {
             true
         } else if let Some(ref x) = *gitignore {
-            if metadata.permissions().mode() == 0o755 || REGEX_GITIGNORE.is_match(path_str) {
-                x.is_match(full_path)
+            if mode == 0o755 || REGEX_GITIGNORE.is_match(&name_bytes) {
+                x.is_match(&path_bytes(full_path))
             } else {
                 false
             }
         } else {
-            path_str == "flxg_stats.txt"
+            name == "flxg_stats.txt"
         }
     }
 }
 
-/// Function to process directory contents and return a `FileTree` struct.
-pub fn read_size(
-    in_paths: &PathBuf,
+/// Function to process directory contents and return their total size, how many entries there
+/// are, and when they were last modified and accessed.
+pub(crate) fn read_size(
+    in_paths: &Path,
     excludes: Option<&Regex>,
     maybe_gitignore: &Option<RegexSet>,
     vimtags: bool,
     artifacts_only: bool,
-) -> FileSize {
+    blocks: bool,
+    links: &HardLinks,
+    device: Option<u64>,
+    symlinks: &Symlinks,
+    cache: Option<&DirCache>,
+    warnings: &Warnings,
+) -> Result<Totals, Internal> {
     // attempt to read the .gitignore
-    let mut size = FileSize::new(0);
+    let mut totals = Totals::default();
     let gitignore = if artifacts_only {
-        mk_ignores(in_paths, maybe_gitignore)
+        mk_ignores(in_paths, maybe_gitignore, warnings)
     } else {
         None
     };
 
+    // don't go around in circles when following symlinks
+    if !symlinks.first_visit(in_paths) {
+        return Ok(totals);
+    }
+
     // try to read directory contents
-    if let Ok(paths) = fs::read_dir(in_paths) {
+    let read = list_dir(in_paths, symlinks, cache, warnings);
+    if let Ok(listing) = read {
+        // count the directory itself
+        totals.count.dirs += 1;
+        totals.own = listing.own;
+        if let Some(own) = listing.own {
+            totals.add(own.dir_totals(blocks));
+        }
+
         // iterate over all the entries in the directory
-        for p in paths {
-            let val = match p {
-                Ok(x) => x,
-                _ => {
-                    panic!("{}", Internal::IoError);
-                }
-            };
-            let path = val.path();
-            let (path_string, bool_loop): (&str, bool) = if let Some(x) = path.as_path().to_str() {
-                let bool_loop = match excludes {
-                    Some(ex) => !ex.is_match(x),
-                    _ => true,
-                };
-                (x, bool_loop)
-            } else {
-                eprintln!(
-                    "{}: skipping invalid unicode filepath at {:?}",
-                    "Warning".yellow(),
-                    path
-                );
-                ("", false)
+        for child in listing.children {
+            let path = in_paths.join(&child.name);
+            let bool_loop = match excludes {
+                Some(ex) => !ex.is_match(&path_bytes(&path)),
+                _ => true,
             };
 
             // only consider path if we're not using regex excludes or
             // if they don't match the exclusion regex
             if bool_loop {
-                let path_type = val.file_type().unwrap(); // ok because we already checked
+                let path_type = child.kind;
 
                 // append file size/name for a file
                 if path_type.is_file() {
                     // if this fails, it's probably because `path` is a broken symlink
-                    if let Ok(metadata) = val.metadata() {
-                        if !artifacts_only || {
-                            is_artifact(
-                                val.file_name().to_str().unwrap(), // ok because we already checked
-                                path_string,
-                                &metadata, // FIXME check metadata only when we know it matches gitignore
-                                vimtags,
-                                &gitignore,
-                            )
-                        } {
+                    if let Some(info) = child.info {
+                        if (!artifacts_only || {
+                            is_artifact(&child.name, &path, info.mode, vimtags, &gitignore)
+                        }) && links.first_link_info(&info)
+                        {
                             // should check size before whether it's an artifact?
-                            let file_size = FileSize::new(metadata.len());
-                            size.add(file_size);
+                            totals.add(info.file_totals(blocks));
                         }
                     }
                 }
                 // otherwise, go deeper
-                else if path_type.is_dir() {
-                    let dir_size = if artifacts_only
-                        && is_project_dir(path_string, val.file_name().to_str().unwrap())
-                    {
-                        read_size(&path, excludes, &gitignore, vimtags, false)
+                else if path_type.is_dir() && !on_other_device(&path, device) {
+                    let dir_totals = if artifacts_only && is_project_dir(&path, &child.name) {
+                        or_warn(
+                            read_size(
+                                &path, excludes, &gitignore, vimtags, false, blocks, links, device,
+                                symlinks, cache, warnings,
+                            ),
+                            warnings,
+                        )
                     } else {
-                        read_size(&path, excludes, &gitignore, vimtags, artifacts_only)
+                        or_warn(
+                            read_size(
+                                &path,
+                                excludes,
+                                &gitignore,
+                                vimtags,
+                                artifacts_only,
+                                blocks,
+                                links,
+                                device,
+                                symlinks,
+                                cache,
+                                warnings,
+                            ),
+                            warnings,
+                        )
                     };
-                    size.add(dir_size);
+                    totals.add(dir_totals);
+                }
+                // symlinks we don't follow, and broken ones, take up an entry
+                else if path_type.is_symlink() && !artifacts_only {
+                    totals.count.symlinks += 1;
+                    if symlinks.follow() {
+                        warnings.warn(&Internal::BrokenSymlink(path));
+                    }
+                }
+                // FIFOs, sockets and devices are left out, but something whose type couldn't be
+                // read is worth a warning
+                else if path_type == Kind::Other {
+                    warnings.warn(&Internal::UnknownFileType(path));
                 }
             }
             /*else {
@@ -290,118 +331,242 @@ pub fn read_size(
     // if we can't read the directory contents, figure out why
     // 1: check the path exists
     else if !in_paths.exists() {
-        eprintln!(
-            "{}: path '{}' does not exist, or you do not have permission to enter.",
-            "Error".red(),
-            &in_paths.display()
-        );
+        return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
     }
     // 2: check the path is actually a directory
     else if !in_paths.is_dir() {
-        eprintln!(
-            "{}: {} is not a directory.",
-            "Error".red(),
-            &in_paths.display()
-        );
-        exit(0x0001);
+        return Err(Internal::NotDirectory(in_paths.to_path_buf()));
     }
-    // 3: otherwise, give a warning about permissions
-    else {
-        eprintln!(
-            "{}: permission denied for directory: {}",
-            "Warning".yellow(),
-            &in_paths.display()
-        );
+    // 3: otherwise, warn about whatever stopped it being read
+    else if let Err(e) = read {
+        totals.count.dirs += 1;
+        warnings.warn(&Internal::from_io(in_paths, e));
     }
 
-    size
+    Ok(totals)
 }
 
 /// Function to process directory contents and return a `FileTree` struct.
-pub fn read_all(
-    in_paths: &PathBuf,
+pub(crate) fn read_all(
+    in_paths: &Path,
     depth: u8,
     max_depth: Option<u8>,
     excludes: Option<&Regex>,
     maybe_gitignore: &Option<RegexSet>,
     vimtags: bool,
     artifacts_only: bool,
-) -> FileTree {
-    // attempt to read the .gitignore
+    blocks: bool,
+    links: &HardLinks,
+    device: Option<u64>,
+    symlinks: &Symlinks,
+    cache: Option<&DirCache>,
+    warnings: &Warnings,
+) -> Result<FileTree, Internal> {
     let mut tree = FileTree::new();
+    let totals = stream_all(
+        in_paths,
+        depth,
+        max_depth,
+        excludes,
+        maybe_gitignore,
+        vimtags,
+        artifacts_only,
+        blocks,
+        links,
+        device,
+        symlinks,
+        cache,
+        warnings,
+        &mut |e| tree.push_post_order(e),
+    )?;
+    tree.add_totals(totals);
+    tree.info = totals.own;
+    Ok(tree)
+}
+
+/// Receives entries from `scan_each` as the walk proceeds. Any `FnMut(NamePair)` closure is a
+/// visitor that reads every directory.
+pub trait Visitor {
+    /// Called with a directory, at the depth its entry will have, before it is read. Returning
+    /// `false` skips it: nothing inside it is read or counted, and it isn't passed to `visit`.
+    fn enter(&mut self, _path: &Path, _depth: u8) -> bool {
+        true
+    }
+
+    /// Called with each file, and with each directory once everything in it has been read.
+    fn visit(&mut self, entry: NamePair);
+}
+
+impl<F> Visitor for F
+where
+    F: FnMut(NamePair),
+{
+    fn visit(&mut self, entry: NamePair) {
+        self(entry)
+    }
+}
+
+/// Function to process directory contents one entry at a time. Each file and directory is passed
+/// to `visitor` as soon as its size is known, with the contents of a directory coming before the
+/// directory itself, and directories the visitor doesn't `enter` are skipped. Returns the totals
+/// for the whole directory, or an error if `in_paths` can't be read; subdirectories that can't be
+/// read are skipped with a warning.
+pub(crate) fn stream_all<V>(
+    in_paths: &Path,
+    depth: u8,
+    max_depth: Option<u8>,
+    excludes: Option<&Regex>,
+    maybe_gitignore: &Option<RegexSet>,
+    vimtags: bool,
+    artifacts_only: bool,
+    blocks: bool,
+    links: &HardLinks,
+    device: Option<u64>,
+    symlinks: &Symlinks,
+    cache: Option<&DirCache>,
+    warnings: &Warnings,
+    visitor: &mut V,
+) -> Result<Totals, Internal>
+where
+    V: Visitor,
+{
+    // attempt to read the .gitignore
+    let mut totals = Totals::default();
     let gitignore = if artifacts_only {
-        mk_ignores(in_paths, maybe_gitignore)
+        mk_ignores(in_paths, maybe_gitignore, warnings)
     } else {
         None
     };
 
+    // don't go around in circles when following symlinks
+    if !symlinks.first_visit(in_paths) {
+        return Ok(totals);
+    }
+
     // try to read directory contents
-    if let Ok(paths) = fs::read_dir(in_paths) {
-        // iterate over all the entries in the directory
-        for p in paths {
-            let val = match p {
-                Ok(x) => x,
-                _ => {
-                    eprintln!("{}:  {:?}.", "Error".red(), p);
-                    exit(0x0001)
-                }
-            };
-            let path = val.path();
-            let (path_string, bool_loop): (&str, bool) = if let Some(x) = path.as_path().to_str() {
-                let bool_loop = match excludes {
-                    Some(ex) => !ex.is_match(x),
-                    _ => true,
-                };
+    let read = list_dir(in_paths, symlinks, cache, warnings);
+    if let Ok(listing) = read {
+        // count the directory itself
+        totals.count.dirs += 1;
+        totals.own = listing.own;
+        if let Some(own) = listing.own {
+            totals.add(own.dir_totals(blocks));
+        }
 
-                (x, bool_loop)
-            } else {
-                eprintln!(
-                    "{}: skipping invalid unicode filepath at {:?}",
-                    "Warning".yellow(),
-                    path
-                );
-                ("", false)
+        // iterate over all the entries in the directory
+        for child in listing.children {
+            let path = in_paths.join(&child.name);
+            let bool_loop = match excludes {
+                Some(ex) => !ex.is_match(&path_bytes(&path)),
+                _ => true,
             };
 
             // only consider path if we're not using regex excludes or if they don't match the
             // exclusion regex
             if bool_loop {
-                let path_type = val.file_type().unwrap(); // ok because we already checked
+                let path_type = child.kind;
 
                 // append file size/name for a file
                 if path_type.is_file() {
                     // if this fails, it's probably because `path` is a broken symlink
-                    if let Ok(metadata) = val.metadata() {
+                    if let Some(info) = child.info {
                         // faster on Windows
-                        if !artifacts_only || {
-                            is_artifact(
-                                val.file_name().to_str().unwrap(), // ok because we already checked
-                                path_string,
-                                &metadata,
-                                vimtags,
-                                &gitignore,
-                            )
-                        } {
-                            let file_size = FileSize::new(metadata.len());
-                            tree.push(path_string.to_string(), file_size, None, depth + 1, false);
+                        if (!artifacts_only || {
+                            is_artifact(&child.name, &path, info.mode, vimtags, &gitignore)
+                        }) && links.first_link_info(&info)
+                        {
+                            let file_totals = info.file_totals(blocks);
+                            totals.add(file_totals);
+                            visitor.visit(
+                                NamePair::new(path.clone(), file_totals.size, depth + 1, false)
+                                    .with_totals(file_totals),
+                            );
                         }
                     }
                 }
                 // otherwise, go deeper
-                else if path_type.is_dir() {
+                else if path_type.is_dir()
+                    && !on_other_device(&path, device)
+                    && visitor.enter(&path, depth + 1)
+                {
                     if let Some(d) = max_depth {
                         if depth + 1 >= d && !artifacts_only {
-                            let dir_size =
-                                { read_size(&path, excludes, &gitignore, vimtags, artifacts_only) };
-                            tree.push(path_string.to_string(), dir_size, None, depth + 1, true);
-                        } else if artifacts_only
-                            && is_project_dir(path_string, val.file_name().to_str().unwrap())
-                        {
-                            let dir_size =
-                                { read_size(&path, excludes, &gitignore, vimtags, false) };
-                            tree.push(path_string.to_string(), dir_size, None, depth + 1, true);
+                            let dir_totals = or_warn(
+                                read_size(
+                                    &path,
+                                    excludes,
+                                    &gitignore,
+                                    vimtags,
+                                    artifacts_only,
+                                    blocks,
+                                    links,
+                                    device,
+                                    symlinks,
+                                    cache,
+                                    warnings,
+                                ),
+                                warnings,
+                            );
+                            totals.add(dir_totals);
+                            visitor.visit(
+                                NamePair::new(path.clone(), dir_totals.size, depth + 1, true)
+                                    .with_totals(dir_totals),
+                            );
+                        } else if artifacts_only && is_project_dir(&path, &child.name) {
+                            let dir_totals = or_warn(
+                                read_size(
+                                    &path, excludes, &gitignore, vimtags, false, blocks, links,
+                                    device, symlinks, cache, warnings,
+                                ),
+                                warnings,
+                            );
+                            totals.add(dir_totals);
+                            visitor.visit(
+                                NamePair::new(path.clone(), dir_totals.size, depth + 1, true)
+                                    .with_totals(dir_totals),
+                            );
                         } else {
-                            let mut subtree = read_all(
+                            let dir_totals = or_warn(
+                                stream_all(
+                                    &path,
+                                    depth + 1,
+                                    max_depth,
+                                    excludes,
+                                    &gitignore,
+                                    vimtags,
+                                    artifacts_only,
+                                    blocks,
+                                    links,
+                                    device,
+                                    symlinks,
+                                    cache,
+                                    warnings,
+                                    visitor,
+                                ),
+                                warnings,
+                            );
+                            totals.add(dir_totals);
+                            visitor.visit(
+                                NamePair::new(path.clone(), dir_totals.size, depth + 1, true)
+                                    .with_totals(dir_totals),
+                            );
+                        }
+                    } else if artifacts_only && is_project_dir(&path, &child.name) {
+                        let dir_totals = or_warn(
+                            read_size(
+                                &path, excludes, &gitignore, vimtags, false, blocks, links, device,
+                                symlinks, cache, warnings,
+                            ),
+                            warnings,
+                        );
+                        totals.add(dir_totals);
+                        visitor.visit(
+                            NamePair::new(path.clone(), dir_totals.size, depth + 1, true)
+                                .with_totals(dir_totals),
+                        );
+                    } else {
+                        let dir_totals = or_warn(
+                            stream_all(
                                 &path,
                                 depth + 1,
                                 max_depth,
@@ -409,41 +574,35 @@ pub fn read_all(
                                 &gitignore,
                                 vimtags,
                                 artifacts_only,
-                            );
-                            let dir_size = subtree.file_size;
-                            tree.push(
-                                path_string.to_string(),
-                                dir_size,
-                                Some(&mut subtree),
-                                depth + 1,
-                                true,
-                            );
-                        }
-                    } else if artifacts_only
-                        && is_project_dir(path_string, val.file_name().to_str().unwrap())
-                    {
-                        let dir_size = { read_size(&path, excludes, &gitignore, vimtags, false) };
-                        tree.push(path_string.to_string(), dir_size, None, depth + 1, true);
-                    } else {
-                        let mut subtree = read_all(
-                            &path,
-                            depth + 1,
-                            max_depth,
-                            excludes,
-                            &gitignore,
-                            vimtags,
-                            artifacts_only,
+                                blocks,
+                                links,
+                                device,
+                                symlinks,
+                                cache,
+                                warnings,
+                                visitor,
+                            ),
+                            warnings,
                         );
-                        let dir_size = subtree.file_size;
-                        tree.push(
-                            path_string.to_string(),
-                            dir_size,
-                            Some(&mut subtree),
-                            depth + 1,
-                            true,
+                        totals.add(dir_totals);
+                        visitor.visit(
+                            NamePair::new(path.clone(), dir_totals.size, depth + 1, true)
+                                .with_totals(dir_totals),
                         );
                     }
                 }
+                // symlinks we don't follow, and broken ones, take up an entry
+                else if path_type.is_symlink() && !artifacts_only {
+                    totals.count.symlinks += 1;
+                    if symlinks.follow() {
+                        warnings.warn(&Internal::BrokenSymlink(path));
+                    }
+                }
+                // FIFOs, sockets and devices are left out, but something whose type couldn't be
+                // read is worth a warning
+                else if path_type == Kind::Other {
+                    warnings.warn(&Internal::UnknownFileType(path));
+                }
             }
         }
     /*else {
@@ -457,217 +616,275 @@ pub fn read_all(
     // if we can't read the directory contents, figure out why
     // 1: check the path exists
     else if !in_paths.exists() {
-        eprintln!(
-            "{}: path '{}' does not exist, or you do not have permission to enter.",
-            "Error".red(),
-            &in_paths.display()
-        );
+        return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
     }
     // 2: check the path is actually a directory
     else if !in_paths.is_dir() {
         if artifacts_only {
-            eprintln!(
-                "{}: {} is not a directory; not searching for artifacts",
-                "Warning".yellow(),
-                &in_paths.display()
-            );
+            warnings.note(format!(
+                "{} is not a directory; not searching for artifacts",
+                in_paths.display()
+            ));
         }
 
-        if let Ok(l) = in_paths.metadata() {
-            let size = l.len();
-            let to_formatted = format!("{}", FileSize::new(size));
-            println!("{}\t {}", &to_formatted.green(), in_paths.display());
-        } else {
-            panic!("{}", Internal::IoError);
+        match in_paths.metadata() {
+            Ok(l) => totals.add(Totals::file(FileSize::new(size(&l, blocks)), &l)),
+            Err(e) => {
+                return Err(Internal::Io {
+                    path: in_paths.to_path_buf(),
+                    source: e,
+                })
+            }
         }
     }
-    // 3: otherwise, give a warning about permissions
-    else {
-        eprintln!(
-            "{}: permission denied for directory: {}",
-            "Warning".yellow(),
-            &in_paths.display()
-        );
+    // 3: otherwise, warn about whatever stopped it being read
+    else if let Err(e) = read {
+        totals.count.dirs += 1;
+        warnings.warn(&Internal::from_io(in_paths, e));
     }
 
-    tree
+    Ok(totals)
 }
 
-/// Function to process directory contents and return a `FileTree` struct.
-pub fn read_no_excludes(
+/// Function to process directory contents and return their total size, how many entries there
+/// are, and when they were last modified and accessed.
+pub(crate) fn read_no_excludes(
     in_paths: &PathBuf,
     _: Option<&Regex>,
     _: &Option<RegexSet>,
     _: bool,
-) -> FileSize {
+    blocks: bool,
+    links: &HardLinks,
+    device: Option<u64>,
+    symlinks: &Symlinks,
+    warnings: &Warnings,
+) -> Result<Totals, Internal> {
     // attempt to read the .gitignore
-    let mut size = FileSize::new(0);
+    let mut totals = Totals::default();
+
+    // don't go around in circles when following symlinks
+    if !symlinks.first_visit(in_paths) {
+        return Ok(totals);
+    }
 
     // try to read directory contents
-    if let Ok(paths) = fs::read_dir(in_paths) {
+    let read = fs::read_dir(in_paths);
+    if let Ok(paths) = read {
+        // count the directory itself
+        totals.count.dirs += 1;
+        if let Ok(m) = in_paths.metadata() {
+            totals.add(Totals::dir_itself(&m, blocks));
+            totals.own = Some(FileInfo::from_metadata(&m));
+        }
+
         // iterate over all the entries in the directory
         for p in paths {
             let val = match p {
                 Ok(x) => x,
-                _ => {
-                    panic!("{}", Internal::IoError);
+                Err(e) => {
+                    warnings.warn(&Internal::Io {
+                        path: in_paths.to_path_buf(),
+                        source: e,
+                    });
+                    continue;
                 }
             };
             // only consider path if we're not using regex excludes or
             // if they don't match the exclusion regex
-            let path_type = val.file_type().unwrap(); // ok because we already checked
+            let path_type = match symlinks.file_type(&val) {
+                Ok(t) => t,
+                Err(e) => {
+                    warnings.warn(&Internal::Io {
+                        path: val.path(),
+                        source: e,
+                    });
+                    continue;
+                }
+            };
 
             // append file size/name for a file
             if path_type.is_file() {
                 // if this fails, it's probably because `path` is a broken symlink
-                if let Ok(metadata) = val.metadata() {
-                    let file_size = FileSize::new(metadata.len());
-                    size.add(file_size);
+                if let Some(metadata) = symlinks.metadata(&val).ok().filter(|m| links.first_link(m))
+                {
+                    totals.add(Totals::file(
+                        FileSize::new(size(&metadata, blocks)),
+                        &metadata,
+                    ));
                 }
             }
             // otherwise, go deeper
-            else if path_type.is_dir() {
-                let dir_size = {
+            else if path_type.is_dir() && !on_other_device(&val.path(), device) {
+                let dir_totals = {
                     let path = val.path();
-                    read_no_excludes(&path, None, &None, false)
+                    or_warn(
+                        read_no_excludes(
+                            &path, None, &None, false, blocks, links, device, symlinks, warnings,
+                        ),
+                        warnings,
+                    )
                 };
-                size.add(dir_size);
+                totals.add(dir_totals);
+            }
+            // symlinks we don't follow, and broken ones, take up an entry
+            else if path_type.is_symlink() {
+                totals.count.symlinks += 1;
+                if symlinks.follow() {
+                    warnings.warn(&Internal::BrokenSymlink(val.path()));
+                }
             }
         }
     }
     // if we can't read the directory contents, figure out why
     // 1: check the path exists
     else if !in_paths.exists() {
-        eprintln!(
-            "{}: path '{}' does not exist, or you do not have permission to enter.",
-            "Error".red(),
-            &in_paths.display()
-        );
+        return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
     }
     // 2: check the path is actually a directory
     else if !in_paths.is_dir() {
-        eprintln!(
-            "{}: {} is not a directory.",
-            "Error".red(),
-            &in_paths.display()
-        );
-        exit(0x0001);
+        return Err(Internal::NotDirectory(in_paths.to_path_buf()));
     }
-    // 3: otherwise, give a warning about permissions
-    else {
-        eprintln!(
-            "{}: permission denied for directory: {}",
-            "Warning".yellow(),
-            &in_paths.display()
-        );
+    // 3: otherwise, warn about whatever stopped it being read
+    else if let Err(e) = read {
+        totals.count.dirs += 1;
+        warnings.warn(&Internal::from_io(in_paths, e));
     }
 
-    size
+    Ok(totals)
 }
 
 /// Function to process directory contents and return a `FileTree` struct.
-pub fn read_all_fast(in_paths: &PathBuf, depth: u8, max_depth: Option<u8>) -> FileTree {
+pub(crate) fn read_all_fast(
+    in_paths: &PathBuf,
+    depth: u8,
+    max_depth: Option<u8>,
+    blocks: bool,
+    links: &HardLinks,
+    device: Option<u64>,
+    symlinks: &Symlinks,
+    warnings: &Warnings,
+) -> Result<FileTree, Internal> {
     // attempt to read the .gitignore
     let mut tree = FileTree::new();
 
+    // don't go around in circles when following symlinks
+    if !symlinks.first_visit(in_paths) {
+        return Ok(tree);
+    }
+
     // try to read directory contents
-    if let Ok(paths) = fs::read_dir(in_paths) {
+    let read = fs::read_dir(in_paths);
+    if let Ok(paths) = read {
+        // count the directory itself
+        tree.count.dirs += 1;
+        if let Ok(m) = in_paths.metadata() {
+            tree.add_totals(Totals::dir_itself(&m, blocks));
+            tree.info = Some(FileInfo::from_metadata(&m));
+        }
+
         // iterate over all the entries in the directory
         for p in paths {
             let val = match p {
                 Ok(x) => x,
-                _ => {
-                    eprintln!("{}: unexpected failure on {:?} failed.", "Error".red(), p);
-                    exit(0x0001)
+                Err(e) => {
+                    warnings.warn(&Internal::Io {
+                        path: in_paths.to_path_buf(),
+                        source: e,
+                    });
+                    continue;
                 }
             };
 
             // only consider path if we're not using regex excludes or if they don't match the
             // exclusion regex
-            let path_type = val.file_type().unwrap(); // ok because we already checked
+            let path_type = match symlinks.file_type(&val) {
+                Ok(t) => t,
+                Err(e) => {
+                    warnings.warn(&Internal::Io {
+                        path: val.path(),
+                        source: e,
+                    });
+                    continue;
+                }
+            };
 
             // append file size/name for a file
             if path_type.is_file() {
                 // if this fails, it's probably because `path` is a broken symlink
-                if let Ok(metadata) = val.metadata() {
+                if let Some(metadata) = symlinks.metadata(&val).ok().filter(|m| links.first_link(m))
+                {
                     // faster on Windows
                     {
                         let path = val.path();
-                        let path_string: &str = if let Some(x) = path.as_path().to_str() {
-                            x
-                        } else {
-                            eprintln!(
-                                "{}: skipping invalid unicode filepath at {:?}",
-                                "Warning".yellow(),
-                                path
-                            );
-                            ""
-                        };
-                        let file_size = FileSize::new(metadata.len());
-                        tree.push(path_string.to_string(), file_size, None, depth + 1, false);
+                        let file_totals =
+                            Totals::file(FileSize::new(size(&metadata, blocks)), &metadata);
+                        tree.add_totals(file_totals);
+                        tree.push_entry(
+                            NamePair::new(path, file_totals.size, depth + 1, false)
+                                .with_totals(file_totals),
+                        );
                     }
                 }
             }
             // otherwise, go deeper
-            else if path_type.is_dir() {
+            else if path_type.is_dir() && !on_other_device(&val.path(), device) {
                 if let Some(d) = max_depth {
                     if depth + 1 >= d {
                         let path = val.path();
-                        let path_string: &str = if let Some(x) = path.as_path().to_str() {
-                            x
-                        } else {
-                            eprintln!(
-                                "{}: skipping invalid unicode filepath at {:?}",
-                                "Warning".yellow(),
-                                path
-                            );
-                            ""
-                        };
-                        let dir_size = { read_no_excludes(&path, None, &None, false) };
-                        tree.push(path_string.to_string(), dir_size, None, depth + 1, true);
+                        let dir_totals = or_warn(
+                            read_no_excludes(
+                                &path, None, &None, false, blocks, links, device, symlinks,
+                                warnings,
+                            ),
+                            warnings,
+                        );
+                        tree.add_totals(dir_totals);
+                        tree.push_entry(
+                            NamePair::new(path, dir_totals.size, depth + 1, true)
+                                .with_totals(dir_totals),
+                        );
                     } else {
                         let path = val.path();
-                        let path_string: &str = if let Some(x) = path.as_path().to_str() {
-                            x
-                        } else {
-                            eprintln!(
-                                "{}: skipping invalid unicode filepath at {:?}",
-                                "Warning".yellow(),
-                                path
-                            );
-                            ""
-                        };
-                        let mut subtree = read_all_fast(&path, depth + 1, max_depth);
-                        let dir_size = subtree.file_size;
-                        tree.push(
-                            path_string.to_string(),
-                            dir_size,
-                            Some(&mut subtree),
-                            depth + 1,
-                            true,
+                        let mut subtree = or_warn(
+                            read_all_fast(
+                                &path,
+                                depth + 1,
+                                max_depth,
+                                blocks,
+                                links,
+                                device,
+                                symlinks,
+                                warnings,
+                            ),
+                            warnings,
                         );
+                        let dir_size = subtree.file_size;
+                        tree.push(path.clone(), dir_size, Some(&mut subtree), depth + 1, true);
                     }
                 } else {
                     let path = val.path();
-                    let path_string: &str = if let Some(x) = path.as_path().to_str() {
-                        x
-                    } else {
-                        eprintln!(
-                            "{}: skipping invalid unicode filepath at {:?}",
-                            "Warning".yellow(),
-                            path
-                        );
-                        ""
-                    };
-                    let mut subtree = read_all_fast(&path, depth + 1, max_depth);
-                    let dir_size = subtree.file_size;
-                    tree.push(
-                        path_string.to_string(),
-                        dir_size,
-                        Some(&mut subtree),
-                        depth + 1,
-                        true,
+                    let mut subtree = or_warn(
+                        read_all_fast(
+                            &path,
+                            depth + 1,
+                            max_depth,
+                            blocks,
+                            links,
+                            device,
+                            symlinks,
+                            warnings,
+                        ),
+                        warnings,
                     );
+                    let dir_size = subtree.file_size;
+                    tree.push(path.clone(), dir_size, Some(&mut subtree), depth + 1, true);
+                }
+            }
+            // symlinks we don't follow, and broken ones, take up an entry
+            else if path_type.is_symlink() {
+                tree.count.symlinks += 1;
+                if symlinks.follow() {
+                    warnings.warn(&Internal::BrokenSymlink(val.path()));
                 }
             }
         }
@@ -675,30 +892,90 @@ pub fn read_all_fast(in_paths: &PathBuf, depth: u8, max_depth: Option<u8>) -> Fi
     // if we can't read the directory contents, figure out why
     // 1: check the path exists
     else if !in_paths.exists() {
-        eprintln!(
-            "{}: path '{}' does not exist, or you do not have permission to enter.",
-            "Error".red(),
-            &in_paths.display()
-        );
+        return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
     }
     // 2: check the path is actually a directory
     else if !in_paths.is_dir() {
-        if let Ok(l) = in_paths.metadata() {
-            let size = l.len();
-            let to_formatted = format!("{}", FileSize::new(size));
-            println!("{}\t {}", &to_formatted.green(), in_paths.display());
-        } else {
-            panic!("{}", Internal::IoError);
+        match in_paths.metadata() {
+            Ok(l) => {
+                let file_totals = Totals::file(FileSize::new(size(&l, blocks)), &l);
+                tree.add_totals(file_totals);
+                tree.info = file_totals.own;
+            }
+            Err(e) => {
+                return Err(Internal::Io {
+                    path: in_paths.to_path_buf(),
+                    source: e,
+                })
+            }
         }
     }
-    // 3: otherwise, give a warning about permissions
-    else {
-        eprintln!(
-            "{}: permission denied for directory: {}",
-            "Warning".yellow(),
-            &in_paths.display()
-        );
+    // 3: otherwise, warn about whatever stopped it being read
+    else if let Err(e) = read {
+        tree.count.dirs += 1;
+        warnings.warn(&Internal::from_io(in_paths, e));
     }
 
-    tree
+    Ok(tree)
+}
+
+/// Find symlinks whose targets don't exist, passing each one to `f` along with where it points.
+/// Symlinks are not followed while looking. Returns an error if `in_paths` can't be read;
+/// subdirectories that can't be read are skipped with a warning.
+pub fn broken_symlinks<F>(
+    in_paths: &Path,
+    excludes: Option<&Regex>,
+    device: Option<u64>,
+    warnings: &Warnings,
+    f: &mut F,
+) -> Result<(), Internal>
+where
+    F: FnMut(&Path, &Path),
+{
+    let read = fs::read_dir(in_paths);
+    if let Ok(paths) = read {
+        for p in paths {
+            let val = match p {
+                Ok(x) => x,
+                Err(e) => {
+                    warnings.warn(&Internal::Io {
+                        path: in_paths.to_path_buf(),
+                        source: e,
+                    });
+                    continue;
+                }
+            };
+            let path = val.path();
+            if excludes.is_some_and(|ex| ex.is_match(&path_bytes(&path))) {
+                continue;
+            }
+            match val.file_type() {
+                Ok(t) if t.is_symlink() && fs::metadata(&path).is_err() => {
+                    if let Ok(target) = fs::read_link(&path) {
+                        f(&path, &target);
+                    }
+                }
+                Ok(t) if t.is_dir() && !on_other_device(&path, device) => or_warn(
+                    broken_symlinks(&path, excludes, device, warnings, f),
+                    warnings,
+                ),
+                Ok(_) => {}
+                Err(e) => warnings.warn(&Internal::Io { path, source: e }),
+            }
+        }
+    }
+    // if we can't read the directory contents, figure out why
+    // 1: check the path exists
+    else if !in_paths.exists() {
+        return Err(Internal::PathDoesNotExist(in_paths.to_path_buf()));
+    }
+    // 2: check the path is actually a directory
+    else if !in_paths.is_dir() {
+        return Err(Internal::NotDirectory(in_paths.to_path_buf()));
+    }
+    // 3: otherwise, warn about whatever stopped it being read
+    else if let Err(e) = read {
+        warnings.warn(&Internal::from_io(in_paths, e));
+    }
+    Ok(())
 }